@@ -0,0 +1,1750 @@
+use crate::config::Config;
+use crate::diagnostics::{self, Extent};
+use crate::line_index::{LineIndex, PositionEncoding};
+use crate::lint::Linter;
+use crate::recovery;
+use crate::shift::Shift;
+use crate::span_index::SpanIndex;
+use crate::suppress::Suppressions;
+use crate::tokens::{self, Tok};
+use crate::*;
+use common::Loc;
+use log::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use syntax::parser::TokenKind;
+use syntax::{self, *};
+use tower_lsp::lsp_types::*;
+use typeck;
+
+/// Give up recovering from syntax errors after this many attempts
+const MAX_RECOVERY: usize = 32;
+
+/// Everything the server knows about one version of a file. Once published,
+/// an analysis is shared behind an `Arc` and never mutated again.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub version: Option<i64>,
+    pub content: String,
+    pub index: LineIndex,
+    pub lexed: Vec<Tok>,
+    /// Whether the file parsed; otherwise only lexer hovers and diagnostics are filled in
+    pub parsed: bool,
+    /// Whether the semantic model below was carried over from an older version that parsed
+    pub stale: bool,
+    /// Code changed since the stale model was computed, about which it knows nothing
+    pub edited: Option<Range>,
+    pub tokens: SpanIndex<Hover>,
+    pub symbols: Vec<SymbolInformation>,
+    pub hovers: SpanIndex<Hover>,
+    pub ranges: Vec<FoldingRange>,
+    pub definitions: SpanIndex<Range>, // ref, def
+    pub diagnostics: Vec<Diagnostic>,
+    pub calls: Vec<CallSite>,
+    pub occurrences: SpanIndex<Occurrence>,
+    pub classes: Vec<ClassInfo>,
+    /// Variables, calls and `this` whose type is a class or an array of a class, with the class
+    pub types: SpanIndex<String>,
+}
+
+/// Outline of a class declaration
+#[derive(Debug, Clone)]
+pub struct ClassInfo {
+    pub name: String,
+    pub parent: Option<String>,
+    /// The whole declaration
+    pub range: Range,
+    /// The name in the declaration
+    pub selection: Range,
+    pub members: Vec<MemberInfo>,
+}
+
+/// Outline of a field or method declaration
+#[derive(Debug, Clone)]
+pub struct MemberInfo {
+    pub name: String,
+    pub method: bool,
+    pub is_static: bool,
+    /// The whole declaration, including a method's body
+    pub range: Range,
+    pub selection: Range,
+    /// Type of the field or signature of the method
+    pub detail: String,
+    /// Parameters and locals of a method
+    pub locals: Vec<LocalInfo>,
+    /// Methods called by a method, with the names at the calls
+    pub calls: Vec<(Symbol, Range)>,
+}
+
+/// Outline of a parameter or local variable declaration
+#[derive(Debug, Clone)]
+pub struct LocalInfo {
+    pub name: String,
+    /// A local's declaration up to its `;`, or a parameter's type and name
+    pub range: Range,
+    pub selection: Range,
+    pub detail: String,
+}
+
+impl ClassInfo {
+    pub fn member(&self, name: &str) -> Option<&MemberInfo> {
+        self.members.iter().find(|member| member.name == name)
+    }
+
+    fn shift(&self, shift: &Shift) -> Option<ClassInfo> {
+        let mut class = self.clone();
+        class.range = shift.range(class.range)?;
+        class.selection = shift.range(class.selection)?;
+        class.members = self
+            .members
+            .iter()
+            .filter_map(|member| {
+                let mut member = member.clone();
+                member.range = shift.range(member.range)?;
+                member.selection = shift.range(member.selection)?;
+                member.locals = member
+                    .locals
+                    .iter()
+                    .filter_map(|local| {
+                        let mut local = local.clone();
+                        local.range = shift.range(local.range)?;
+                        local.selection = shift.range(local.selection)?;
+                        Some(local)
+                    })
+                    .collect();
+                member.calls = member
+                    .calls
+                    .iter()
+                    .filter_map(|(callee, range)| Some((callee.clone(), shift.range(*range)?)))
+                    .collect();
+                Some(member)
+            })
+            .collect();
+        Some(class)
+    }
+}
+
+/// What a name refers to. Members and classes are identified by name so that
+/// they can be matched across files, which are checked separately.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Symbol {
+    /// A local variable or parameter, by the name in its declaration
+    Local(Range),
+    /// A field, by the class declaring it
+    Field {
+        class: String,
+        name: String,
+    },
+    /// A method, by the class declaring it
+    Method {
+        class: String,
+        name: String,
+    },
+    Class(String),
+}
+
+/// A name in the code, resolved to what it refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occurrence {
+    pub symbol: Symbol,
+    pub declaration: bool,
+    /// Whether a variable is given a value here, by its declaration or an assignment
+    pub write: bool,
+}
+
+/// A call whose callee the type checker resolved
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    pub loc: Loc,
+    /// Name of the callee in its declaration
+    pub callee: Range,
+    pub params: Vec<Range>,
+    pub args: Vec<Loc>,
+}
+
+impl Analysis {
+    /// Carry over the semantic model of `previous` when this version did not parse,
+    /// moving its ranges through the edits made since and marking it stale
+    pub fn inherit(&mut self, previous: &Analysis) {
+        if self.parsed || !(previous.parsed || previous.stale) {
+            return;
+        }
+        let shift = Shift::between(&previous.index, &self.index);
+        self.stale = true;
+        self.edited = Some(match previous.edited {
+            Some(edited) => shift.cover(edited),
+            None => shift.edited(),
+        });
+        self.symbols = previous
+            .symbols
+            .iter()
+            .filter_map(|symbol| {
+                let mut symbol = symbol.clone();
+                symbol.location.range = shift.range(symbol.location.range)?;
+                Some(symbol)
+            })
+            .collect();
+        self.ranges = previous
+            .ranges
+            .iter()
+            .filter_map(|folding| shift.folding_range(folding))
+            .collect();
+        self.hovers = previous.hovers.filter_map(|range, hover| {
+            let mut hover = hover.clone();
+            hover.range = hover.range.and_then(|range| shift.range(range));
+            if !previous.stale {
+                hover.contents = stale(hover.contents);
+            }
+            Some((shift.range(*range)?, hover))
+        });
+        self.definitions = previous
+            .definitions
+            .filter_map(|range, def| Some((shift.range(*range)?, shift.range(*def)?)));
+        self.classes = previous
+            .classes
+            .iter()
+            .filter_map(|class| class.shift(&shift))
+            .collect();
+        self.types = previous
+            .types
+            .filter_map(|range, class| Some((shift.range(*range)?, class.clone())));
+        self.occurrences = previous.occurrences.filter_map(|range, occurrence| {
+            let mut occurrence = occurrence.clone();
+            if let Symbol::Local(declaration) = occurrence.symbol {
+                occurrence.symbol = Symbol::Local(shift.range(declaration)?);
+            }
+            Some((shift.range(*range)?, occurrence))
+        });
+    }
+
+    /// Whether the semantic model says anything about `position`, which is not the
+    /// case in code edited since a stale model was computed
+    pub fn known(&self, position: Position) -> bool {
+        match self.edited {
+            Some(edited) => position < edited.start || position > edited.end,
+            None => true,
+        }
+    }
+
+    /// Classes containing their fields and methods, and methods their parameters and locals
+    pub fn outline(&self) -> Vec<DocumentSymbol> {
+        let mut outline = Vec::new();
+        for class in self.classes.iter() {
+            let mut members = Vec::new();
+            for member in class.members.iter() {
+                let locals = member.locals.iter().map(|local| DocumentSymbol {
+                    name: local.name.clone(),
+                    detail: Some(local.detail.clone()),
+                    kind: SymbolKind::Variable,
+                    deprecated: None,
+                    range: local.range,
+                    selection_range: local.selection,
+                    children: None,
+                });
+                members.push(DocumentSymbol {
+                    name: member.name.clone(),
+                    detail: Some(member.detail.clone()),
+                    kind: if member.method {
+                        SymbolKind::Method
+                    } else {
+                        SymbolKind::Field
+                    },
+                    deprecated: None,
+                    range: member.range,
+                    selection_range: member.selection,
+                    children: if member.method {
+                        Some(locals.collect())
+                    } else {
+                        None
+                    },
+                });
+            }
+            let mut detail = class
+                .parent
+                .as_ref()
+                .map(|parent| format!("extends {}", parent));
+            if self.stale {
+                let parent = detail.map_or(String::new(), |parent| parent + " ");
+                detail = Some(parent + "(outdated)");
+            }
+            outline.push(DocumentSymbol {
+                name: class.name.clone(),
+                detail,
+                kind: SymbolKind::Class,
+                deprecated: None,
+                range: class.range,
+                selection_range: class.selection,
+                children: Some(members),
+            });
+        }
+        outline
+    }
+}
+
+fn stale(contents: HoverContents) -> HoverContents {
+    let note = MarkedString::from_markdown(String::from(
+        "*Outdated: the file has syntax errors, showing the last version that parsed*",
+    ));
+    match contents {
+        HoverContents::Scalar(marked) => HoverContents::Array(vec![marked, note]),
+        HoverContents::Array(mut marked) => {
+            marked.push(note);
+            HoverContents::Array(marked)
+        }
+        contents => contents,
+    }
+}
+
+fn field_name<'a>(field: &FieldDef<'a>) -> &'a str {
+    match field {
+        FieldDef::FuncDef(func) => func.name,
+        FieldDef::VarDef(var) => var.name,
+    }
+}
+
+/// Find a field or method by name in a class or its ancestors
+fn lookup<'a>(
+    program: &Program<'a>,
+    class: &'a ClassDef<'a>,
+    name: &str,
+) -> Option<&'a FieldDef<'a>> {
+    let mut class = Some(class);
+    // bounded, in case the inheritance is cyclic
+    for _ in 0..program.class.len() {
+        let current = class?;
+        if let Some(field) = current.field.iter().find(|field| field_name(field) == name) {
+            return Some(field);
+        }
+        class = current.parent_ref.get();
+    }
+    None
+}
+
+/// Whether a method shares its slot with a method of an ancestor or a descendant of its class
+fn is_overridden<'a>(program: &Program<'a>, class: &ClassDef<'a>, name: &str) -> bool {
+    let declares = |class: &ClassDef<'a>| {
+        class
+            .field
+            .iter()
+            .any(|field| matches!(field, FieldDef::FuncDef(_)) && field_name(field) == name)
+    };
+    let inherited = class
+        .parent_ref
+        .get()
+        .and_then(|parent| lookup(program, parent, name));
+    if let Some(FieldDef::FuncDef(_)) = inherited {
+        return true;
+    }
+    program.class.iter().any(|other| {
+        let mut ancestor = other.parent_ref.get();
+        // bounded, in case the inheritance is cyclic
+        for _ in 0..program.class.len() {
+            match ancestor {
+                Some(current) if std::ptr::eq(current, class) => return declares(*other),
+                Some(current) => ancestor = current.parent_ref.get(),
+                None => break,
+            }
+        }
+        false
+    })
+}
+
+fn tok_range(state: &Analysis, tok: &Tok) -> Range {
+    Range {
+        start: state.index.offset_position(tok.start),
+        end: state.index.offset_position(tok.end),
+    }
+}
+
+fn is_name(state: &Analysis, tok: &Tok, name: &str) -> bool {
+    tok.ty == TokenKind::Id && state.content.get(tok.start..tok.end) == Some(name)
+}
+
+/// Range of `name` among the first tokens from `loc`. Member accesses are located
+/// at their `.` and class types at their `class` keyword, so the name may come later.
+fn name_range(state: &Analysis, loc: &Loc, name: &str) -> Range {
+    let first = tokens::at(&state.lexed, state.index.loc_offset(loc));
+    match state.lexed[first..]
+        .iter()
+        .take(3)
+        .find(|tok| is_name(state, tok, name))
+    {
+        Some(tok) => tok_range(state, tok),
+        None => range_name(&state.index, loc, name),
+    }
+}
+
+/// Range of the parent class name in the header of the class declared at `loc`
+fn extends_range(state: &Analysis, loc: &Loc, parent: &str) -> Option<Range> {
+    let first = tokens::at(&state.lexed, state.index.loc_offset(loc));
+    let header = state.lexed[first..]
+        .iter()
+        .take_while(|tok| tok.ty != TokenKind::LBrc && tok.ty != TokenKind::_Eof);
+    let mut after_extends = false;
+    for tok in header {
+        if after_extends && is_name(state, tok, parent) {
+            return Some(tok_range(state, tok));
+        }
+        after_extends = tok.ty == TokenKind::Extends;
+    }
+    None
+}
+
+/// Range of the class name closing `instanceof(expr, name)` at `loc`
+fn class_test_range(state: &Analysis, loc: &Loc, name: &str) -> Option<Range> {
+    let first = tokens::at(&state.lexed, state.index.loc_offset(loc));
+    let mut depth = 0;
+    for (i, tok) in state.lexed.iter().enumerate().skip(first) {
+        match tok.ty {
+            TokenKind::LPar => depth += 1,
+            TokenKind::RPar => {
+                depth -= 1;
+                if depth == 0 {
+                    let name_tok = &state.lexed[i.checked_sub(1)?];
+                    return if is_name(state, name_tok, name) {
+                        Some(tok_range(state, name_tok))
+                    } else {
+                        None
+                    };
+                }
+            }
+            TokenKind::_Eof => break,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Range of the member declared with the name at `selection`: from the token after
+/// the previous member up to the `;` of a field or the closing brace of a method
+fn member_range(state: &Analysis, selection: Range) -> Range {
+    let lexed = &state.lexed;
+    let mut first = tokens::at(lexed, state.index.offset(selection.start));
+    while first > 0 {
+        match lexed[first - 1].ty {
+            TokenKind::Semi | TokenKind::LBrc | TokenKind::RBrc => break,
+            _ => first -= 1,
+        }
+    }
+    let (start, end) = tokens::stmt_span(lexed, lexed[first].start);
+    Range {
+        start: state.index.offset_position(start),
+        end: state.index.offset_position(end),
+    }
+}
+
+/// Range of a local declaration from its type, up to the `;` or, for a parameter, the name
+fn local_range(state: &Analysis, ty: &Loc, selection: Range, param: bool) -> Range {
+    let start = state.index.loc_offset(ty);
+    let end = if param {
+        state.index.offset(selection.end)
+    } else {
+        tokens::stmt_span(&state.lexed, start).1
+    };
+    Range {
+        start: state.index.offset_position(start),
+        end: state.index.offset_position(end),
+    }
+}
+
+/// Ranges whose diagnostics are dropped after recovery: each member with blanked
+/// code in it, or the blanked code itself when it is outside any member
+fn dropped(state: &Analysis, blanked: &[(usize, usize)]) -> Vec<Range> {
+    blanked
+        .iter()
+        .map(|&(start, end)| {
+            let start = state.index.offset_position(start);
+            let members = state.classes.iter().flat_map(|class| class.members.iter());
+            members
+                .map(|member| member.range)
+                .find(|range| range.start <= start && start < range.end)
+                .unwrap_or_else(|| Range {
+                    start,
+                    end: state.index.offset_position(end),
+                })
+        })
+        .collect()
+}
+
+/// Fields of a class and its ancestors, nearest first
+fn fields<'a>(program: &Program<'a>, class: &'a ClassDef<'a>) -> Vec<&'a VarDef<'a>> {
+    let mut fields = Vec::new();
+    let mut class = Some(class);
+    for _ in 0..program.class.len() {
+        let current = match class {
+            Some(current) => current,
+            None => break,
+        };
+        for field in current.field.iter() {
+            if let FieldDef::VarDef(var) = field {
+                fields.push(*var);
+            }
+        }
+        class = current.parent_ref.get();
+    }
+    fields
+}
+
+/// Name of the variable in an assignment of the form `x = x` or `this.x = this.x`
+fn self_assigned<'a>(assign: &Assign<'a>) -> Option<&'a str> {
+    match (&assign.dst.kind, &assign.src.kind) {
+        (ExprKind::VarSel(dst), ExprKind::VarSel(src)) => {
+            let same_owner = match (&dst.owner, &src.owner) {
+                (None, None) => true,
+                (Some(dst), Some(src)) => {
+                    matches!(
+                        (&dst.kind, &src.kind),
+                        (ExprKind::This(_), ExprKind::This(_))
+                    )
+                }
+                _ => false,
+            };
+            if same_owner && std::ptr::eq(dst.var.get()?, src.var.get()?) {
+                Some(dst.name)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The class whose body contains `loc`
+fn enclosing_class<'a>(program: &Program<'a>, loc: Loc) -> Option<&'a ClassDef<'a>> {
+    program.class.iter().cloned().find(|class| {
+        (class.loc.0, class.loc.1) <= (loc.0, loc.1) && (loc.0, loc.1) <= (class.end.0, class.end.1)
+    })
+}
+
+/// Location of the first token of an expression; binary operators, calls and
+/// member accesses are located at their operator
+fn expr_start<'a>(expr: &Expr<'a>) -> Loc {
+    match &expr.kind {
+        ExprKind::VarSel(VarSel {
+            owner: Some(owner), ..
+        }) => expr_start(owner),
+        ExprKind::IndexSel(indexsel) => expr_start(&indexsel.arr),
+        ExprKind::Call(call) => expr_start(&call.func),
+        ExprKind::Binary(bin) => expr_start(&bin.l),
+        _ => expr.loc,
+    }
+}
+
+/// Location of the first token of a statement; assignments are located at the `=`
+fn stmt_start<'a>(stmt: &Stmt<'a>) -> Loc {
+    match &stmt.kind {
+        StmtKind::Assign(assign) => expr_start(&assign.dst),
+        StmtKind::ExprEval(expr) => expr_start(expr),
+        _ => stmt.loc,
+    }
+}
+
+/// Class of a declared type, with its array dimensions
+fn syn_class<'a>(ty: &SynTy<'a>) -> Option<(String, u32)> {
+    match &ty.kind {
+        SynTyKind::Named(name) => Some((String::from(*name), ty.arr)),
+        _ => None,
+    }
+}
+
+/// Static class of an expression with its array dimensions, read from the declarations.
+/// `None` stands for `this`, the receiver of a call without one.
+fn static_class<'a>(expr: Option<&Expr<'a>>, state: &Analysis) -> Option<(String, u32)> {
+    let current = || Some((state.classes.last()?.name.clone(), 0));
+    let kind = match expr {
+        Some(expr) => &expr.kind,
+        None => return current(),
+    };
+    match kind {
+        ExprKind::This(_) => current(),
+        ExprKind::NewClass(new) => Some((String::from(new.name), 0)),
+        ExprKind::ClassCast(cast) => Some((String::from(cast.name), 0)),
+        ExprKind::NewArray(new) => {
+            let (class, arr) = syn_class(&new.elem)?;
+            Some((class, arr + 1))
+        }
+        ExprKind::VarSel(varsel) => match varsel.var.get() {
+            Some(var) => syn_class(&var.syn_ty),
+            None => Some((String::from(static_receiver(expr?)?), 0)),
+        },
+        ExprKind::Call(call) => syn_class(&call.func_ref.get()?.ret),
+        ExprKind::IndexSel(indexsel) => {
+            let (class, arr) = static_class(Some(&*indexsel.arr), state)?;
+            Some((class, arr.checked_sub(1)?))
+        }
+        _ => None,
+    }
+}
+
+/// Class named by the receiver of a static call `A.m()`: a bare name that is not a variable
+fn static_receiver<'a>(owner: &Expr<'a>) -> Option<&'a str> {
+    match &owner.kind {
+        ExprKind::VarSel(varsel) if varsel.owner.is_none() && varsel.var.get().is_none() => {
+            Some(varsel.name)
+        }
+        _ => None,
+    }
+}
+
+/// Static class of the receiver of a call, for when the type checker could not
+/// resolve the call, e.g. because the class is declared in another file
+fn receiver_class<'a>(owner: Option<&Expr<'a>>, state: &Analysis) -> Option<String> {
+    match static_class(owner, state)? {
+        (class, 0) => Some(class),
+        _ => None,
+    }
+}
+
+/// Whether a body has no statements besides empty ones
+fn is_empty<'a>(block: &Block<'a>) -> bool {
+    block
+        .stmt
+        .iter()
+        .all(|stmt| matches!(stmt.kind, StmtKind::Skip(_)))
+}
+
+/// Walks the lexer tokens and the type checked AST of a file
+#[derive(Debug)]
+pub struct Analyzer {
+    uri: Url,
+    encoding: PositionEncoding,
+    config: Arc<Config>,
+    lint: RefCell<Linter>,
+    /// First token of each expression located at an operator, by that location
+    starts: RefCell<HashMap<(u32, u32), Loc>>,
+}
+
+impl Analyzer {
+    pub fn new(uri: Url, encoding: PositionEncoding, config: Arc<Config>) -> Analyzer {
+        Analyzer {
+            uri,
+            encoding,
+            config,
+            lint: RefCell::new(Linter::default()),
+            starts: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn expr<'a>(&self, expr: &Expr<'a>, state: &mut Analysis) {
+        let start = expr_start(expr);
+        if start != expr.loc {
+            // the outermost expression at a location is walked first
+            self.starts
+                .borrow_mut()
+                .entry((expr.loc.0, expr.loc.1))
+                .or_insert(start);
+        }
+        match &expr.kind {
+            ExprKind::VarSel(varsel) => {
+                self.varsel(&expr.loc, varsel, false, state);
+            }
+            ExprKind::IndexSel(indexsel) => {
+                self.expr(&indexsel.arr, state);
+                self.expr(&indexsel.idx, state);
+            }
+            ExprKind::Call(call) => {
+                if let ExprKind::VarSel(varsel) = &call.func.kind {
+                    let receiver = varsel.owner.as_ref().and_then(|owner| {
+                        let name = static_receiver(owner)?;
+                        Some((name_range(state, &owner.loc, name), name))
+                    });
+                    if let Some((range, name)) = receiver {
+                        // a resolved call through a class is static; an unresolved
+                        // one may go through a class of another file
+                        if call.func_ref.get().map_or(true, |func| func.static_) {
+                            self.class_ref(range, name, state);
+                        }
+                    }
+                    let range = name_range(state, &call.func.loc, varsel.name);
+                    // unresolved calls are looked up by the receiver's class in all files
+                    let class = match call.func_ref.get() {
+                        Some(func) => {
+                            let definition = name_range(state, &func.loc, func.name);
+                            state.definitions.push(range, definition);
+                            if let Some((class, _)) = syn_class(&func.ret) {
+                                state.types.push(range, class);
+                            }
+                            func.class.get().map(|class| String::from(class.name))
+                        }
+                        None => receiver_class(varsel.owner.as_ref().map(|owner| &**owner), state),
+                    };
+                    if let Some(class) = class {
+                        let symbol = Symbol::Method {
+                            class,
+                            name: String::from(varsel.name),
+                        };
+                        let caller = state
+                            .classes
+                            .last_mut()
+                            .and_then(|class| class.members.last_mut());
+                        if let Some(caller) = caller {
+                            caller.calls.push((symbol.clone(), range));
+                        }
+                        self.occurrence(range, symbol, false, state);
+                    }
+                }
+                if let Some(func) = call.func_ref.get() {
+                    state.calls.push(CallSite {
+                        loc: expr.loc,
+                        callee: range_name(&state.index, &func.loc, func.name),
+                        params: func
+                            .param
+                            .iter()
+                            .map(|param| range_name(&state.index, &param.loc, param.name))
+                            .collect(),
+                        args: call.arg.iter().map(|arg| arg.loc).collect(),
+                    });
+                }
+                self.expr(&call.func, state);
+                for arg in call.arg.iter() {
+                    self.expr(&arg, state);
+                }
+            }
+            ExprKind::Unary(un) => {
+                self.expr(&un.r, state);
+            }
+            ExprKind::Binary(bin) => {
+                self.expr(&bin.l, state);
+                self.expr(&bin.r, state);
+            }
+            ExprKind::This(_) => {
+                if let Some((class, _)) = static_class(Some(expr), state) {
+                    let (start, end) =
+                        tokens::token_span(&state.lexed, state.index.loc_offset(&expr.loc));
+                    let range = Range {
+                        start: state.index.offset_position(start),
+                        end: state.index.offset_position(end),
+                    };
+                    state.types.push(range, class);
+                }
+            }
+            ExprKind::NewClass(new) => {
+                let range = name_range(state, &expr.loc, new.name);
+                self.class_ref(range, new.name, state);
+            }
+            ExprKind::NewArray(new) => {
+                self.syn_ty(&new.elem, state);
+                self.expr(&new.len, state);
+            }
+            ExprKind::ClassTest(test) => {
+                self.expr(&test.expr, state);
+                if let Some(range) = class_test_range(state, &expr.loc, test.name) {
+                    self.class_ref(range, test.name, state);
+                }
+            }
+            ExprKind::ClassCast(cast) => {
+                let range = name_range(state, &expr.loc, cast.name);
+                self.class_ref(range, cast.name, state);
+                self.expr(&cast.expr, state);
+            }
+            _ => {}
+        }
+    }
+
+    fn occurrence(&self, range: Range, symbol: Symbol, declaration: bool, state: &mut Analysis) {
+        let write = declaration && matches!(symbol, Symbol::Local(_) | Symbol::Field { .. });
+        state.occurrences.push(
+            range,
+            Occurrence {
+                symbol,
+                declaration,
+                write,
+            },
+        );
+    }
+
+    fn class_ref(&self, range: Range, name: &str, state: &mut Analysis) {
+        self.occurrence(range, Symbol::Class(String::from(name)), false, state);
+    }
+
+    fn syn_ty<'a>(&self, ty: &SynTy<'a>, state: &mut Analysis) {
+        if let SynTyKind::Named(name) = &ty.kind {
+            let range = name_range(state, &ty.loc, name);
+            self.class_ref(range, name, state);
+        }
+    }
+
+    /// Fields are identified by their class, locals and parameters by their declaration
+    fn var_symbol<'a>(&self, var: &VarDef<'a>, state: &Analysis) -> Symbol {
+        match var.owner.get() {
+            Some(ScopeOwner::Class(class)) => Symbol::Field {
+                class: String::from(class.name),
+                name: String::from(var.name),
+            },
+            _ => Symbol::Local(name_range(state, &var.loc, var.name)),
+        }
+    }
+
+    /// A variable that is read, or assigned if `write` is set
+    fn varsel<'a>(&self, loc: &Loc, varsel: &VarSel<'a>, write: bool, state: &mut Analysis) {
+        state.hovers.push(
+            range_name(&state.index, loc, varsel.name),
+            Hover {
+                contents: HoverContents::Scalar(MarkedString::from_markdown(format!(
+                    "{}: {:?}",
+                    varsel.name,
+                    varsel.ty.get(),
+                ))),
+                range: Some(range(&state.index, &loc)),
+            },
+        );
+        if let Some(expr) = &varsel.owner {
+            self.expr(&expr, state);
+        }
+        if let Some(var) = &varsel.var.get() {
+            debug!("var {} {:?} {:?}", var.name, var.loc, var.ty.get());
+            // a variable that is only ever assigned is still unused
+            if !write {
+                self.lint.borrow_mut().use_var(&var.loc);
+            }
+            let symbol = self.var_symbol(var, state);
+            let range = name_range(state, loc, varsel.name);
+            if let Some((class, _)) = syn_class(&var.syn_ty) {
+                state.types.push(range, class);
+            }
+            state.occurrences.push(
+                range,
+                Occurrence {
+                    symbol,
+                    declaration: false,
+                    write,
+                },
+            );
+            state.definitions.push(
+                range_name(&state.index, &loc, varsel.name),
+                range_name(&state.index, &var.loc, var.name),
+            );
+        }
+    }
+
+    fn var<'a>(&self, var: &VarDef<'a>, state: &mut Analysis) {
+        state.hovers.push(
+            range_name(&state.index, &var.loc, var.name),
+            Hover {
+                contents: HoverContents::Scalar(MarkedString::from_markdown(format!(
+                    "{}: {:?}",
+                    var.name,
+                    var.ty.get(),
+                ))),
+                range: Some(range(&state.index, &var.loc)),
+            },
+        );
+        let symbol = self.var_symbol(var, state);
+        let range = name_range(state, &var.loc, var.name);
+        if let Symbol::Local(_) = symbol {
+            let param = matches!(var.owner.get(), Some(ScopeOwner::Param(_)));
+            let local = LocalInfo {
+                name: String::from(var.name),
+                range: local_range(state, &var.syn_ty.loc, range, param),
+                selection: range,
+                detail: format!("{:?}", var.ty.get()),
+            };
+            let member = state
+                .classes
+                .last_mut()
+                .and_then(|class| class.members.last_mut());
+            if let Some(member) = member {
+                member.locals.push(local);
+            }
+        }
+        if let Some((class, _)) = syn_class(&var.syn_ty) {
+            state.types.push(range, class);
+        }
+        self.occurrence(range, symbol, true, state);
+        self.syn_ty(&var.syn_ty, state);
+    }
+
+    fn stmt<'a>(&self, stmt: &Stmt<'a>, state: &mut Analysis) {
+        match &stmt.kind {
+            StmtKind::Assign(assign) => {
+                if let Some(name) = self_assigned(assign) {
+                    self.lint.borrow_mut().self_assignment(
+                        &state.index,
+                        &stmt_start(stmt),
+                        &stmt.loc,
+                        name,
+                    );
+                }
+                match &assign.dst.kind {
+                    ExprKind::VarSel(varsel) => self.varsel(&assign.dst.loc, varsel, true, state),
+                    _ => self.expr(&assign.dst, state),
+                }
+                self.expr(&assign.src, state);
+            }
+            StmtKind::LocalVarDef(var) => {
+                self.lint.borrow_mut().declare_local(
+                    &var.loc,
+                    var.name,
+                    range_name(&state.index, &var.loc, var.name),
+                );
+                self.var(var, state);
+                if let Some((_loc, expr)) = &var.init {
+                    self.expr(expr, state);
+                }
+            }
+            StmtKind::ExprEval(expr) => {
+                self.expr(expr, state);
+            }
+            StmtKind::If(i) => {
+                self.expr(&i.cond, state);
+                if is_empty(&i.on_true) {
+                    self.lint
+                        .borrow_mut()
+                        .empty_body(&state.index, &stmt.loc, "if");
+                }
+                self.block(&i.on_true, state);
+                if let Some(f) = &i.on_false {
+                    self.block(f, state);
+                }
+            }
+            StmtKind::While(w) => {
+                self.expr(&w.cond, state);
+                if is_empty(&w.body) {
+                    self.lint
+                        .borrow_mut()
+                        .empty_body(&state.index, &stmt.loc, "while");
+                }
+                self.block(&w.body, state);
+            }
+            StmtKind::For(f) => {
+                self.stmt(&f.init, state);
+                self.expr(&f.cond, state);
+                self.stmt(&f.update, state);
+                self.block(&f.body, state);
+            }
+            StmtKind::Return(Some(expr)) => {
+                self.expr(&expr, state);
+            }
+            StmtKind::Print(exprs) => {
+                for expr in exprs.iter() {
+                    self.expr(&expr, state);
+                }
+            }
+            StmtKind::Block(block) => {
+                self.block(&block, state);
+            }
+            _ => {}
+        }
+    }
+
+    fn block<'a>(&self, block: &Block<'a>, state: &mut Analysis) {
+        let mut reachable = true;
+        for (i, stmt) in block.stmt.iter().enumerate() {
+            self.stmt(stmt, state);
+            let jumps = matches!(stmt.kind, StmtKind::Return(_) | StmtKind::Break(_));
+            if reachable && jumps {
+                reachable = false;
+                if let (Some(first), Some(last)) = (block.stmt.get(i + 1), block.stmt.last()) {
+                    self.lint
+                        .borrow_mut()
+                        .unreachable(&state.index, &stmt_start(first), &last.loc);
+                }
+            }
+        }
+    }
+
+    fn field<'a>(
+        &self,
+        program: &Program<'a>,
+        class: &ClassDef<'a>,
+        field: &FieldDef<'a>,
+        state: &mut Analysis,
+    ) {
+        match field {
+            syntax::FieldDef::FuncDef(func) => {
+                state.symbols.push(SymbolInformation {
+                    name: func.name.to_string(),
+                    kind: SymbolKind::Method,
+                    deprecated: None,
+                    location: Location {
+                        uri: self.uri.clone(),
+                        range: range(&state.index, &func.loc),
+                    },
+                    container_name: Some(class.name.to_string()),
+                });
+                state.hovers.push(
+                    range_name(&state.index, &func.loc, func.name),
+                    Hover {
+                        contents: HoverContents::Scalar(MarkedString::from_markdown(format!(
+                            "{}: {:?}",
+                            func.name,
+                            syntax::ty::Ty::mk_func(func)
+                        ))),
+                        range: Some(range(&state.index, &func.loc)),
+                    },
+                );
+                let symbol = Symbol::Method {
+                    class: String::from(class.name),
+                    name: String::from(func.name),
+                };
+                let range = name_range(state, &func.loc, func.name);
+                self.member(
+                    func.name,
+                    true,
+                    func.static_,
+                    range,
+                    &syntax::ty::Ty::mk_func(func),
+                    state,
+                );
+                self.occurrence(range, symbol, true, state);
+                self.syn_ty(&func.ret, state);
+                // the parameters of an overriding or overridden method are fixed by its signature
+                let fixed = is_overridden(program, class, func.name);
+                for param in func.param.iter() {
+                    if !fixed {
+                        self.lint.borrow_mut().declare_param(
+                            &param.loc,
+                            param.name,
+                            range_name(&state.index, &param.loc, param.name),
+                        );
+                    }
+                    self.var(param, state);
+                }
+                self.block(&func.body, state);
+                self.lint.borrow_mut().leave_func();
+            }
+            syntax::FieldDef::VarDef(var) => {
+                state.symbols.push(SymbolInformation {
+                    name: var.name.to_string(),
+                    kind: SymbolKind::Field,
+                    deprecated: None,
+                    location: Location {
+                        uri: self.uri.clone(),
+                        range: range(&state.index, &var.loc),
+                    },
+                    container_name: Some(class.name.to_string()),
+                });
+                let range = name_range(state, &var.loc, var.name);
+                self.member(var.name, false, false, range, &var.ty.get(), state);
+                self.var(var, state);
+            }
+        }
+    }
+
+    fn class<'a>(&self, program: &Program<'a>, class: &ClassDef<'a>, state: &mut Analysis) {
+        let class_range = range2(&state.index, &class.loc, &class.end);
+        state.symbols.push(SymbolInformation {
+            name: class.name.to_string(),
+            kind: SymbolKind::Class,
+            deprecated: None,
+            location: Location {
+                uri: self.uri.clone(),
+                range: class_range,
+            },
+            container_name: None,
+        });
+        state.hovers.push(
+            range_name(&state.index, &class.loc, class.name),
+            Hover {
+                contents: HoverContents::Scalar(MarkedString::from_markdown(
+                    class.name.to_string(),
+                )),
+                range: Some(class_range),
+            },
+        );
+        let range = name_range(state, &class.loc, class.name);
+        state.classes.push(ClassInfo {
+            name: String::from(class.name),
+            parent: class.parent.map(String::from),
+            range: class_range,
+            selection: range,
+            members: Vec::new(),
+        });
+        self.occurrence(range, Symbol::Class(String::from(class.name)), true, state);
+        if let Some(parent) = class.parent {
+            if let Some(range) = extends_range(state, &class.loc, parent) {
+                self.class_ref(range, parent, state);
+            }
+        }
+        state.ranges.push(FoldingRange {
+            start_line: (class.loc.0 - 1) as u64,
+            start_character: None,
+            end_line: (class.end.0 - 1) as u64,
+            end_character: None,
+            kind: Some(FoldingRangeKind::Region),
+        });
+
+        for field in class.field.iter() {
+            self.field(program, class, field, state);
+        }
+    }
+
+    fn member(
+        &self,
+        name: &str,
+        method: bool,
+        is_static: bool,
+        selection: Range,
+        ty: &dyn fmt::Debug,
+        state: &mut Analysis,
+    ) {
+        let range = member_range(state, selection);
+        if let Some(class) = state.classes.last_mut() {
+            class.members.push(MemberInfo {
+                name: String::from(name),
+                method,
+                is_static,
+                range,
+                selection,
+                detail: format!("{:?}", ty),
+                locals: Vec::new(),
+                calls: Vec::new(),
+            });
+        }
+    }
+
+    fn program<'a>(&self, program: &Program<'a>, state: &mut Analysis) {
+        for class in program.class.iter() {
+            let fields = fields(program, class)
+                .into_iter()
+                .map(|var| {
+                    let declaration = range_name(&state.index, &var.loc, var.name);
+                    (String::from(var.name), declaration)
+                })
+                .collect();
+            self.lint.borrow_mut().enter_class(fields);
+            self.class(program, class, state);
+        }
+
+        // class names declared in this file; the others are looked up in the workspace on request
+        let classes: Vec<(Range, Range)> = state
+            .occurrences
+            .iter()
+            .filter_map(|(range, occurrence)| match &occurrence.symbol {
+                Symbol::Class(name) if !occurrence.declaration => {
+                    let class = state.classes.iter().find(|class| class.name == *name)?;
+                    Some((*range, class.selection))
+                }
+                _ => None,
+            })
+            .collect();
+        for (reference, definition) in classes {
+            state.definitions.push(reference, definition);
+        }
+    }
+
+    fn tokens(&self, content: &str, state: &mut Analysis) {
+        let mut tokens = syntax::parser::Lexer::new(content.as_bytes());
+        loop {
+            use syntax::parser::TokenKind::*;
+            let tok = tokens.next();
+            if tok.ty == _Eof {
+                break;
+            }
+
+            if tok.ty == Id
+                || tok.ty == Le
+                || tok.ty == Ge
+                || tok.ty == Eq
+                || tok.ty == Ne
+                || tok.ty == And
+                || tok.ty == Add
+                || tok.ty == Sub
+                || tok.ty == Mul
+                || tok.ty == Div
+                || tok.ty == Mod
+                || tok.ty == Assign
+                || tok.ty == Lt
+                || tok.ty == Gt
+                || tok.ty == Dot
+                || tok.ty == Comma
+                || tok.ty == Semi
+                || tok.ty == Not
+                || tok.ty == LPar
+                || tok.ty == RPar
+                || tok.ty == LBrk
+                || tok.ty == RBrk
+                || tok.ty == LBrc
+                || tok.ty == RBrc
+                || tok.ty == Colon
+            {
+                continue;
+            }
+
+            let range = token(&state.index, &tok);
+            state.tokens.push(
+                range,
+                Hover {
+                    contents: HoverContents::Scalar(MarkedString::from_markdown(match tok.ty {
+                        IntLit => format!("Integer Literal"),
+                        StringLit => format!("String Literal"),
+                        UntermString => format!("Unterminated String Literal"),
+                        _ => format!("{:?}", tok.ty),
+                    })),
+                    range: None,
+                },
+            );
+        }
+    }
+
+    /// Collect every syntax error by repeatedly blanking out the code around the
+    /// errors and parsing again. The program parsed from the repaired text, which keeps
+    /// all well-formed classes and members, is handed to `parsed` with the byte ranges
+    /// that were blanked. Returns `None` if recovery gave up.
+    fn recover<R>(
+        &self,
+        content: &str,
+        tokens: &[Tok],
+        state: &mut Analysis,
+        parsed: impl for<'a> FnOnce(&'a Program<'a>, &[(usize, usize)], &mut Analysis) -> R,
+    ) -> Option<R> {
+        let mut text = String::from(content);
+        let mut blanked = Vec::new();
+        let mut reported: Vec<Loc> = Vec::new();
+        for _ in 0..MAX_RECOVERY {
+            let alloc = syntax::ASTAlloc::default();
+            let result = syntax::parser::work(&text, &alloc);
+            let locs = match result {
+                Ok(program) => return Some(parsed(program, &blanked, state)),
+                Err(errors) => {
+                    for err in errors.0.iter() {
+                        if !reported.contains(&err.0) {
+                            reported.push(err.0);
+                            let diag = self.diagnostic(&state.index, tokens, err);
+                            state.diagnostics.push(diag);
+                        }
+                    }
+                    errors.0.iter().map(|err| err.0).collect::<Vec<_>>()
+                }
+            };
+            let (repaired, ranges) = recovery::blank(&text, &state.index, &locs)?;
+            text = repaired;
+            blanked.extend(ranges);
+        }
+        None
+    }
+
+    /// Type check and walk a program whose code in the `blanked` byte ranges was dropped.
+    /// Returns whether every kind of diagnostic was computed, or `None` if cancelled.
+    fn check<'a>(
+        &self,
+        program: &'a Program<'a>,
+        blanked: &[(usize, usize)],
+        cancelled: &dyn Fn() -> bool,
+        state: &mut Analysis,
+    ) -> Option<bool> {
+        if cancelled() {
+            return None;
+        }
+        let alloc = typeck::TypeCkAlloc::default();
+        let checked = typeck::work(program, &alloc);
+        if cancelled() {
+            return None;
+        }
+
+        // symbols, hovers and ranges
+        state.parsed = true;
+        self.program(program, state);
+        state.symbols.reverse();
+        debug!("hovers {:?}", state.hovers);
+        debug!("def {:?}", state.definitions);
+
+        let lint = self.lint.replace(Linter::default());
+        if !self.config.stage.typeck() {
+            return Some(false);
+        }
+        let mut diagnostics = Vec::new();
+        if let Err(errors) = checked {
+            for err in errors.0.iter() {
+                let mut diag = self.diagnostic(&state.index, &state.lexed, err);
+                diag.related_information = self.related(program, state, err);
+                diagnostics.push(diag);
+            }
+        }
+        diagnostics.extend(lint.finish(&self.uri));
+        // in a member with dropped code, most type errors and lints follow from it
+        let dropped = dropped(state, blanked);
+        state
+            .diagnostics
+            .extend(diagnostics.into_iter().filter(|diag| {
+                let start = diag.range.start;
+                !dropped
+                    .iter()
+                    .any(|range| range.start <= start && start < range.end)
+            }));
+        Some(blanked.is_empty())
+    }
+
+    fn diagnostic<T: fmt::Debug>(
+        &self,
+        index: &LineIndex,
+        tokens: &[Tok],
+        err: &common::Error<'_, T>,
+    ) -> Diagnostic {
+        let (start, end) = match diagnostics::extent(&err.1) {
+            Extent::Token => tokens::token_span(tokens, index.loc_offset(&err.0)),
+            Extent::Expr => {
+                // underline from the first token rather than the operator
+                let starts = self.starts.borrow();
+                let start = starts.get(&(err.0 .0, err.0 .1)).unwrap_or(&err.0);
+                tokens::expr_span(tokens, index.loc_offset(start))
+            }
+        };
+        Diagnostic {
+            range: Range {
+                start: index.offset_position(start),
+                end: index.offset_position(end),
+            },
+            severity: Some(diagnostics::severity(&err.1)),
+            code: Some(NumberOrString::String(String::from(diagnostics::code(
+                &err.1,
+            )))),
+            source: Some(String::from(diagnostics::SOURCE)),
+            message: diagnostics::message(&err.1),
+            related_information: None,
+            tags: None,
+        }
+    }
+
+    /// Point a type error at the other half of the conflict: the earlier declaration,
+    /// the overridden member or the callee's declaration
+    fn related<'a, T>(
+        &self,
+        program: &Program<'a>,
+        state: &Analysis,
+        err: &common::Error<'_, T>,
+    ) -> Option<Vec<DiagnosticRelatedInformation>> {
+        use common::ErrorKind::*;
+        let index = &state.index;
+        let (range, message) = match &err.1 {
+            ConflictDeclaration { prev, name } => (
+                range_name(index, prev, name),
+                format!("'{}' is first declared here", name),
+            ),
+            BadOverride { method, parent } => {
+                let class = program.class.iter().find(|class| class.name == *parent)?;
+                match lookup(program, class, method)? {
+                    FieldDef::FuncDef(func) => (
+                        range_name(index, &func.loc, func.name),
+                        format!("overridden method '{}' is declared here", method),
+                    ),
+                    FieldDef::VarDef(_) => return None,
+                }
+            }
+            OverrideVar(name) => {
+                let class = enclosing_class(program, err.0)?.parent_ref.get()?;
+                match lookup(program, class, name)? {
+                    FieldDef::VarDef(var) => (
+                        range_name(index, &var.loc, var.name),
+                        format!("'{}' is first declared in a parent class here", name),
+                    ),
+                    FieldDef::FuncDef(_) => return None,
+                }
+            }
+            ArgcMismatch { name, .. } => {
+                let call = state.calls.iter().find(|call| call.loc == err.0)?;
+                (call.callee, format!("'{}' is declared here", name))
+            }
+            ArgMismatch { loc, .. } => {
+                // reported either at the call or at the argument itself
+                let i = (*loc as usize).checked_sub(1)?;
+                let call = state
+                    .calls
+                    .iter()
+                    .find(|call| call.loc == err.0 || call.args.get(i) == Some(&err.0))?;
+                let param = call.params.get(i)?;
+                (*param, format!("parameter {} is declared here", loc))
+            }
+            _ => return None,
+        };
+        Some(vec![DiagnosticRelatedInformation {
+            location: Location {
+                uri: self.uri.clone(),
+                range,
+            },
+            message,
+        }])
+    }
+
+    /// Lex, parse and type check `content`. Returns `None` as soon as `cancelled`
+    /// reports that the result is no longer wanted.
+    pub fn analyze(
+        &self,
+        content: &str,
+        version: Option<i64>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<Analysis> {
+        let mut state = Analysis::default();
+        state.version = version;
+        state.content = String::from(content);
+        state.index = LineIndex::new(content, self.encoding);
+
+        // hovers
+        self.tokens(content, &mut state);
+        if cancelled() {
+            return None;
+        }
+
+        // symbols
+        let lexed = tokens::lex(content, &state.index);
+        self.lint.replace(Linter::new(lexed.clone()));
+        state.lexed = lexed.clone();
+        let checked = self.recover(content, &lexed, &mut state, |program, blanked, state| {
+            self.check(program, blanked, cancelled, state)
+        });
+        // whether every kind of diagnostic was computed
+        let complete = match checked {
+            Some(Some(complete)) => complete,
+            Some(None) => return None,
+            None => false,
+        };
+        let diagnostics = std::mem::take(&mut state.diagnostics);
+        let diagnostics =
+            Suppressions::scan(&state.index, &state.lexed).apply(diagnostics, complete);
+        state.diagnostics = self.config.apply(diagnostics);
+        state.tokens.build();
+        state.hovers.build();
+        state.definitions.build();
+        state.occurrences.build();
+        state.types.build();
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Analyze `text` as the file `uri` with the default config
+    pub(crate) fn analyze(uri: &str, text: &str) -> Analysis {
+        let uri = Url::parse(uri).unwrap();
+        let config = Arc::new(Config::default());
+        let analyzer = Analyzer::new(uri, PositionEncoding::Utf16, config);
+        analyzer.analyze(text, None, &|| false).unwrap()
+    }
+
+    /// Range of the `n`th occurrence of `needle` in the analyzed text
+    pub(crate) fn find(file: &Analysis, needle: &str, n: usize) -> Range {
+        let (start, _) = file.content.match_indices(needle).nth(n).unwrap();
+        Range {
+            start: file.index.offset_position(start),
+            end: file.index.offset_position(start + needle.len()),
+        }
+    }
+
+    /// Location and message of the only related information of the diagnostic with `code`
+    fn related(file: &Analysis, code: &str) -> (Range, String) {
+        let related = diagnostic(file, code).related_information.as_ref().unwrap();
+        assert_eq!(related.len(), 1);
+        assert_eq!(related[0].location.uri.as_str(), "file:///main.decaf");
+        (related[0].location.range, related[0].message.clone())
+    }
+
+    /// The diagnostic with `code`, which must be reported once
+    pub(crate) fn diagnostic<'a>(file: &'a Analysis, code: &str) -> &'a Diagnostic {
+        let code = NumberOrString::String(String::from(code));
+        let mut found = file
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.code.as_ref() == Some(&code));
+        let diagnostic = found.next().unwrap();
+        assert!(found.next().is_none());
+        diagnostic
+    }
+
+    #[test]
+    fn messages() {
+        let text = "class Main {
+    static void main() {
+        count = 1;
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        let undeclared = diagnostic(&file, "undeclared-var");
+        assert_eq!(undeclared.message, "undeclared variable 'count'");
+        assert_eq!(undeclared.severity, Some(DiagnosticSeverity::Error));
+        assert_eq!(undeclared.source, Some(String::from(diagnostics::SOURCE)));
+    }
+
+    #[test]
+    fn ranges() {
+        let text = "class Main {
+    static void main() {
+        class Fibonacci fib = new Fibonacci();
+        count = fib.size;
+        if (1 + 2) {
+            Print(fib.get(1));
+        }
+    }
+}
+
+class Fibonacci {
+    int get(int index) {
+        return index;
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        // a name is underlined as a whole
+        assert_eq!(
+            diagnostic(&file, "undeclared-var").range,
+            find(&file, "count", 0)
+        );
+        // a member access is located at its `.`, but the name is underlined
+        assert_eq!(
+            diagnostic(&file, "no-such-field").range,
+            find(&file, "size", 0)
+        );
+        // an operand is underlined from its first token rather than the operator
+        assert_eq!(
+            diagnostic(&file, "test-not-bool").range,
+            find(&file, "1 + 2", 0)
+        );
+    }
+
+    #[test]
+    fn related_declarations() {
+        let text = "class Main {
+    static void main() {
+        int count;
+        int count;
+    }
+}
+
+class A {
+    int get(int index) {
+        return index;
+    }
+}
+
+class B extends A {
+    bool get(int index) {
+        return true;
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        assert_eq!(
+            diagnostic(&file, "conflict-declaration").range,
+            find(&file, "count", 1)
+        );
+        assert_eq!(
+            related(&file, "conflict-declaration"),
+            (
+                find(&file, "count", 0),
+                String::from("'count' is first declared here")
+            )
+        );
+        assert_eq!(
+            related(&file, "bad-override"),
+            (
+                find(&file, "get", 0),
+                String::from("overridden method 'get' is declared here")
+            )
+        );
+    }
+
+    #[test]
+    fn related_callee() {
+        let text = "class Main {
+    static void main() {
+        class A a = new A();
+        a.get(1, 2);
+    }
+}
+
+class A {
+    int get(int index) {
+        return index;
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        assert_eq!(
+            related(&file, "argc-mismatch"),
+            (
+                find(&file, "get", 1),
+                String::from("'get' is declared here")
+            )
+        );
+    }
+
+    #[test]
+    fn writes() {
+        let text = "class Main {
+    static void main() {
+        int count = 1;
+        count = count + 1;
+        Print(count);
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        let local = Symbol::Local(find(&file, "count", 0));
+        let mut occurrences: Vec<(Range, bool, bool)> = file
+            .occurrences
+            .iter()
+            .filter(|(_, occurrence)| occurrence.symbol == local)
+            .map(|(range, occurrence)| (*range, occurrence.declaration, occurrence.write))
+            .collect();
+        occurrences.sort_by_key(|(range, _, _)| range.start);
+        assert_eq!(
+            occurrences,
+            vec![
+                (find(&file, "count", 0), true, true),
+                (find(&file, "count", 1), false, true),
+                (find(&file, "count", 2), false, false),
+                (find(&file, "count", 3), false, false),
+            ]
+        );
+        // declaring a method does not write anything
+        let (_, main) = file
+            .occurrences
+            .innermost(find(&file, "main", 0).start)
+            .unwrap();
+        assert!(main.declaration && !main.write);
+    }
+
+    #[test]
+    fn class_definitions() {
+        let text = "class Main {
+    static void main() {
+        class B b = new B();
+    }
+}
+
+class A {
+}
+
+class B extends A {
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        let definition = |range: Range| {
+            let (_, definition) = file.definitions.innermost(range.start).unwrap();
+            *definition
+        };
+        // in a type, an expression and an extends clause
+        assert_eq!(definition(find(&file, "B", 0)), find(&file, "B", 2));
+        assert_eq!(definition(find(&file, "B", 1)), find(&file, "B", 2));
+        assert_eq!(definition(find(&file, "A", 1)), find(&file, "A", 0));
+    }
+
+    #[test]
+    fn method_definitions() {
+        let file = analyze(
+            "file:///fibonacci.decaf",
+            include_str!("../fibonacci.decaf"),
+        );
+        let get = Range {
+            start: find(&file, "get(int", 0).start,
+            end: find(&file, "(int", 0).start,
+        };
+        let symbol = Symbol::Method {
+            class: String::from("Fibonacci"),
+            name: String::from("get"),
+        };
+        // through a variable of the class and from inside it
+        for call in ["F.get", "return get"].iter() {
+            let range = find(&file, call, 0);
+            let position = Position {
+                line: range.end.line,
+                character: range.end.character - 1,
+            };
+            let (_, definition) = file.definitions.innermost(position).unwrap();
+            assert_eq!(*definition, get);
+            let (_, occurrence) = file.occurrences.innermost(position).unwrap();
+            assert_eq!(occurrence.symbol, symbol);
+        }
+        let main = file.classes[0].member("main").unwrap();
+        assert_eq!(main.calls.len(), 2);
+        assert!(main.calls.iter().all(|(callee, _)| *callee == symbol));
+    }
+
+    #[test]
+    fn types() {
+        let text = "class Main {
+    static void main() {
+        class A a = new A();
+        a.me().run();
+    }
+}
+
+class A {
+    class A me() {
+        return this;
+    }
+
+    void run() {
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        let class = |needle: &str| {
+            let (_, class) = file.types.innermost(find(&file, needle, 0).start)?;
+            Some(class.as_str())
+        };
+        // a variable, the result of a call and `this`
+        assert_eq!(class("a.me"), Some("A"));
+        assert_eq!(class("me()."), Some("A"));
+        assert_eq!(class("this"), Some("A"));
+        assert_eq!(class("run();"), None);
+    }
+
+    #[test]
+    fn outline() {
+        let text = "class Main {
+    static void main() {
+        int count = 1;
+        Print(count);
+    }
+}
+
+class A extends Main {
+    int size;
+
+    int get(int index) {
+        return index;
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        let outline = file.outline();
+        let names = |symbols: &[DocumentSymbol]| -> Vec<(String, SymbolKind)> {
+            symbols
+                .iter()
+                .map(|symbol| (symbol.name.clone(), symbol.kind))
+                .collect()
+        };
+        assert_eq!(
+            names(&outline),
+            vec![
+                (String::from("Main"), SymbolKind::Class),
+                (String::from("A"), SymbolKind::Class)
+            ]
+        );
+        assert_eq!(outline[1].detail, Some(String::from("extends Main")));
+        let members = outline[1].children.as_ref().unwrap();
+        assert_eq!(
+            names(members),
+            vec![
+                (String::from("size"), SymbolKind::Field),
+                (String::from("get"), SymbolKind::Method)
+            ]
+        );
+        // members and locals cover their whole declaration
+        assert_eq!(members[0].range, find(&file, "int size;", 0));
+        assert_eq!(members[0].selection_range, find(&file, "size", 0));
+        assert!(members[0].children.is_none());
+        assert_eq!(
+            members[1].range,
+            find(
+                &file,
+                "int get(int index) {\n        return index;\n    }",
+                0
+            )
+        );
+        let params = members[1].children.as_ref().unwrap();
+        assert_eq!(params[0].range, find(&file, "int index", 0));
+        let main = &outline[0].children.as_ref().unwrap()[0];
+        let locals = main.children.as_ref().unwrap();
+        assert_eq!(
+            names(locals),
+            vec![(String::from("count"), SymbolKind::Variable)]
+        );
+        assert_eq!(locals[0].range, find(&file, "int count = 1;", 0));
+    }
+}
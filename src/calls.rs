@@ -0,0 +1,212 @@
+use crate::analysis::{Analysis, MemberInfo, Symbol};
+use crate::hierarchy::{self, Class, Hierarchy, Key};
+use crate::protocol::*;
+use serde_json::json;
+use tower_lsp::lsp_types::*;
+
+fn method<'a>(hierarchy: &Hierarchy<'a>, key: &Key) -> Option<(Class<'a>, &'a MemberInfo)> {
+    match &key.1 {
+        Symbol::Method { name, .. } => {
+            let class = hierarchy.class(key)?;
+            let member = class.1.member(name).filter(|member| member.method)?;
+            Some((class, member))
+        }
+        _ => None,
+    }
+}
+
+fn item((uri, class): Class, member: &MemberInfo) -> CallHierarchyItem {
+    CallHierarchyItem {
+        name: member.name.clone(),
+        kind: SymbolKind::Method,
+        detail: Some(class.name.clone()),
+        uri: uri.clone(),
+        range: member.range,
+        selection_range: member.selection,
+        data: Some(json!({ "class": class.name, "name": member.name })),
+    }
+}
+
+/// The method an item stands for, from its data or else its name and class.
+/// The class is declared in the item's file.
+fn item_key(item: &CallHierarchyItem) -> Option<Key> {
+    let field = |key: &str| {
+        let data = item.data.as_ref()?;
+        Some(String::from(data.get(key)?.as_str()?))
+    };
+    let symbol = Symbol::Method {
+        class: field("class").or_else(|| item.detail.clone())?,
+        name: field("name").unwrap_or_else(|| item.name.clone()),
+    };
+    Some((Some(item.uri.clone()), symbol))
+}
+
+/// The method declared or called at `position` of the file `uri`
+pub fn prepare(
+    hierarchy: &Hierarchy,
+    uri: &Url,
+    file: &Analysis,
+    position: Position,
+) -> Option<Vec<CallHierarchyItem>> {
+    let (_, occurrence) = file.occurrences.innermost(position)?;
+    let (class, member) = method(hierarchy, &hierarchy.resolve(uri, &occurrence.symbol))?;
+    Some(vec![item(class, member)])
+}
+
+/// Methods calling the item's method, including calls through a superclass
+/// that may dispatch to it
+pub fn incoming(
+    hierarchy: &Hierarchy,
+    target: &CallHierarchyItem,
+) -> Vec<CallHierarchyIncomingCall> {
+    let (target, target_member) = match item_key(target).and_then(|key| method(hierarchy, &key)) {
+        Some(method) => method,
+        None => return Vec::new(),
+    };
+    let reaches = |uri: &Url, callee: &Symbol| {
+        let key = hierarchy.resolve(uri, callee);
+        match (&key.1, hierarchy.class(&key)) {
+            (Symbol::Method { name, .. }, Some(class)) => {
+                *name == target_member.name
+                    && hierarchy
+                        .dispatch(class, name)
+                        .into_iter()
+                        .any(|class| hierarchy::same(class, target))
+            }
+            _ => false,
+        }
+    };
+    let mut calls = Vec::new();
+    for (uri, class) in hierarchy.classes() {
+        for member in class.members.iter().filter(|member| member.method) {
+            let from_ranges: Vec<Range> = member
+                .calls
+                .iter()
+                .filter(|(callee, _)| reaches(uri, callee))
+                .map(|(_, range)| *range)
+                .collect();
+            if !from_ranges.is_empty() {
+                calls.push(CallHierarchyIncomingCall {
+                    from: item((uri, class), member),
+                    from_ranges,
+                });
+            }
+        }
+    }
+    calls
+}
+
+/// Methods called by the item's method. A call may run any override of its
+/// callee, so all of them are listed.
+pub fn outgoing(
+    hierarchy: &Hierarchy,
+    source: &CallHierarchyItem,
+) -> Vec<CallHierarchyOutgoingCall> {
+    let ((uri, _), member) = match item_key(source).and_then(|key| method(hierarchy, &key)) {
+        Some(method) => method,
+        None => return Vec::new(),
+    };
+    let mut targets: Vec<(Class, &MemberInfo, Vec<Range>)> = Vec::new();
+    for (callee, range) in member.calls.iter() {
+        let key = hierarchy.resolve(uri, callee);
+        let (class, name) = match (&key.1, hierarchy.class(&key)) {
+            (Symbol::Method { name, .. }, Some(class)) => (class, name),
+            _ => continue,
+        };
+        for target in hierarchy.dispatch(class, name) {
+            let callee = match target.1.member(name).filter(|member| member.method) {
+                Some(callee) => callee,
+                None => continue,
+            };
+            match targets
+                .iter_mut()
+                .find(|(_, other, _)| std::ptr::eq(*other, callee))
+            {
+                Some((_, _, ranges)) => ranges.push(*range),
+                None => targets.push((target, callee, vec![*range])),
+            }
+        }
+    }
+    targets
+        .into_iter()
+        .map(|(class, callee, from_ranges)| CallHierarchyOutgoingCall {
+            to: item(class, callee),
+            from_ranges,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::tests::{analyze, find};
+    use std::sync::Arc;
+
+    const PROGRAM: &str = "class Main {
+    static void main() {
+        class Shape shape = new Square();
+        Print(shape.area());
+    }
+}
+
+class Shape {
+    int area() {
+        return 1;
+    }
+}
+
+class Square extends Shape {
+    int area() {
+        return 2;
+    }
+}
+";
+
+    fn names(items: Vec<&CallHierarchyItem>) -> Vec<(&str, &str)> {
+        items
+            .into_iter()
+            .map(|item| {
+                (
+                    item.detail.as_ref().map_or("", String::as_str),
+                    item.name.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calls() {
+        let uri = Url::parse("file:///main.decaf").unwrap();
+        let entries = vec![(uri.clone(), Arc::new(analyze(uri.as_str(), PROGRAM)))];
+        let hierarchy = Hierarchy::new(&entries);
+        let file = &entries[0].1;
+        let item_at = |needle: &str, n: usize| {
+            let position = find(file, needle, n).start;
+            let mut items = prepare(&hierarchy, &uri, file, position).unwrap();
+            assert_eq!(items.len(), 1);
+            items.remove(0)
+        };
+        let call = find(file, "area", 0);
+        let square = item_at("area", 2);
+        assert_eq!(names(vec![&square]), vec![("Square", "area")]);
+        assert_eq!(square.selection_range, find(file, "area", 2));
+
+        // a call through `Shape` may run the method of `Square`
+        let callers = incoming(&hierarchy, &square);
+        assert_eq!(
+            names(callers.iter().map(|caller| &caller.from).collect()),
+            vec![("Main", "main")]
+        );
+        assert_eq!(callers[0].from_ranges, vec![call]);
+
+        let main = item_at("main", 0);
+        let callees = outgoing(&hierarchy, &main);
+        assert_eq!(
+            names(callees.iter().map(|callee| &callee.to).collect()),
+            vec![("Shape", "area"), ("Square", "area")]
+        );
+        assert!(callees
+            .iter()
+            .all(|callee| callee.from_ranges == vec![call]));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::analysis::{Analysis, Symbol};
+use crate::hierarchy::{Class, Hierarchy};
+use crate::protocol::*;
+use std::collections::HashSet;
+use tower_lsp::lsp_types::*;
+
+fn item((uri, class): Class) -> TypeHierarchyItem {
+    TypeHierarchyItem {
+        name: class.name.clone(),
+        kind: SymbolKind::Class,
+        detail: class
+            .parent
+            .as_ref()
+            .map(|parent| format!("extends {}", parent)),
+        uri: uri.clone(),
+        range: class.range,
+        selection_range: class.selection,
+        data: None,
+    }
+}
+
+/// The class an item stands for, declared in the item's file
+fn item_class<'a>(hierarchy: &Hierarchy<'a>, item: &TypeHierarchyItem) -> Option<Class<'a>> {
+    hierarchy.declared(&item.uri, &item.name)
+}
+
+/// The class named at `position` of the file `uri`
+pub fn prepare(
+    hierarchy: &Hierarchy,
+    uri: &Url,
+    file: &Analysis,
+    position: Position,
+) -> Option<Vec<TypeHierarchyItem>> {
+    let (_, occurrence) = file.occurrences.innermost(position)?;
+    match &occurrence.symbol {
+        Symbol::Class(name) => Some(vec![item(hierarchy.get(uri, name)?)]),
+        _ => None,
+    }
+}
+
+pub fn supertypes(hierarchy: &Hierarchy, class: &TypeHierarchyItem) -> Vec<TypeHierarchyItem> {
+    item_class(hierarchy, class)
+        .and_then(|class| hierarchy.parent(class))
+        .map(item)
+        .into_iter()
+        .collect()
+}
+
+pub fn subtypes(hierarchy: &Hierarchy, class: &TypeHierarchyItem) -> Vec<TypeHierarchyItem> {
+    item_class(hierarchy, class)
+        .map(|class| hierarchy.subclasses(class))
+        .unwrap_or_default()
+        .into_iter()
+        .map(item)
+        .collect()
+}
+
+fn node<'a>(
+    hierarchy: &Hierarchy<'a>,
+    class: Class<'a>,
+    seen: &mut HashSet<(&'a Url, &'a str)>,
+) -> ClassNode {
+    let (uri, info) = class;
+    seen.insert((uri, info.name.as_str()));
+    let mut children = Vec::new();
+    for subclass in hierarchy.subclasses(class) {
+        if !seen.contains(&(subclass.0, subclass.1.name.as_str())) {
+            children.push(node(hierarchy, subclass, seen));
+        }
+    }
+    ClassNode {
+        name: info.name.clone(),
+        uri: uri.clone(),
+        range: info.range,
+        selection_range: info.selection,
+        children,
+    }
+}
+
+/// Every class of the workspace below its superclass. Classes whose superclass is
+/// unknown are roots, and so is one class of each inheritance cycle.
+pub fn forest(hierarchy: &Hierarchy) -> Vec<ClassNode> {
+    let mut seen = HashSet::new();
+    let mut roots = Vec::new();
+    for class in hierarchy.classes() {
+        if hierarchy.parent(class).is_none() {
+            roots.push(node(hierarchy, class, &mut seen));
+        }
+    }
+    for class in hierarchy.classes() {
+        if !seen.contains(&(class.0, class.1.name.as_str())) {
+            roots.push(node(hierarchy, class, &mut seen));
+        }
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::tests::{analyze, find};
+    use std::sync::Arc;
+
+    const PROGRAM: &str = "class Main {
+    static void main() {
+    }
+}
+
+class A {
+}
+
+class B extends A {
+}
+
+class C extends A {
+}
+
+class D extends E {
+}
+
+class E extends D {
+}
+";
+
+    fn names(items: &[TypeHierarchyItem]) -> Vec<&str> {
+        items.iter().map(|item| item.name.as_str()).collect()
+    }
+
+    /// Names of the classes in a forest, each followed by its subclasses in brackets
+    fn outline(nodes: &[ClassNode]) -> String {
+        let mut parts = Vec::new();
+        for node in nodes {
+            if node.children.is_empty() {
+                parts.push(node.name.clone());
+            } else {
+                parts.push(format!("{}[{}]", node.name, outline(&node.children)));
+            }
+        }
+        parts.join(" ")
+    }
+
+    #[test]
+    fn hierarchy() {
+        let uri = Url::parse("file:///main.decaf").unwrap();
+        let entries = vec![(uri.clone(), Arc::new(analyze(uri.as_str(), PROGRAM)))];
+        let hierarchy = Hierarchy::new(&entries);
+        let file = &entries[0].1;
+        let position = find(file, "B", 0).start;
+        let items = prepare(&hierarchy, &uri, file, position).unwrap();
+        assert_eq!(names(&items), vec!["B"]);
+        assert_eq!(items[0].detail, Some(String::from("extends A")));
+        assert_eq!(items[0].selection_range, find(file, "B", 0));
+
+        let parents = supertypes(&hierarchy, &items[0]);
+        assert_eq!(names(&parents), vec!["A"]);
+        assert_eq!(names(&subtypes(&hierarchy, &parents[0])), vec!["B", "C"]);
+        assert_eq!(
+            names(&supertypes(&hierarchy, &parents[0])),
+            Vec::<&str>::new()
+        );
+
+        // one class of the cycle becomes a root
+        assert_eq!(outline(&forest(&hierarchy)), "A[B C] Main D[E]");
+    }
+}
@@ -0,0 +1,283 @@
+use crate::diagnostics::{self, SOURCE};
+use crate::line_index::{LineIndex, PositionEncoding};
+use crate::lint;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::*;
+
+/// Name of the project config file, looked up in the workspace root
+pub const FILE_NAME: &str = "decaf.toml";
+
+/// Severity a diagnostic is reported with, or `Off` to drop it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Off,
+    Hint,
+    Warning,
+    Error,
+}
+
+impl Level {
+    pub fn severity(self) -> Option<DiagnosticSeverity> {
+        match self {
+            Level::Off => None,
+            Level::Hint => Some(DiagnosticSeverity::Hint),
+            Level::Warning => Some(DiagnosticSeverity::Warning),
+            Level::Error => Some(DiagnosticSeverity::Error),
+        }
+    }
+}
+
+/// Assignment of the Decaf course the code is written for. Programs of the
+/// parser stages are not expected to type check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub enum Stage {
+    #[serde(rename = "PA1-A")]
+    Pa1a,
+    #[serde(rename = "PA1-B")]
+    Pa1b,
+    #[serde(rename = "PA2")]
+    Pa2,
+    #[serde(rename = "PA3")]
+    Pa3,
+    #[serde(rename = "PA4")]
+    Pa4,
+    #[serde(rename = "PA5")]
+    Pa5,
+}
+
+impl Default for Stage {
+    fn default() -> Stage {
+        Stage::Pa5
+    }
+}
+
+impl Stage {
+    /// Whether type errors and lints are reported
+    pub fn typeck(self) -> bool {
+        self >= Stage::Pa2
+    }
+}
+
+/// Contents of `decaf.toml`, e.g.
+///
+/// ```toml
+/// stage = "PA2"
+/// roots = ["src"]
+///
+/// [diagnostics]
+/// unused-variable = "off"
+/// argc-mismatch = "warning"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub stage: Stage,
+    /// Directories holding the sources, relative to the config file. Defaults to the workspace roots.
+    #[serde(default)]
+    pub roots: Vec<PathBuf>,
+    /// Level of each diagnostic, by code
+    #[serde(default)]
+    pub diagnostics: HashMap<String, Level>,
+}
+
+/// Why a config file was rejected, located by 0-based line and byte column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl ConfigError {
+    /// Diagnostic on the config file, underlining the rest of the offending line
+    pub fn diagnostic(&self, index: &LineIndex) -> Diagnostic {
+        Diagnostic {
+            range: Range {
+                start: index.position(self.line, self.col),
+                end: index.position(self.line, usize::max_value()),
+            },
+            severity: Some(DiagnosticSeverity::Error),
+            code: Some(NumberOrString::String(String::from("invalid-config"))),
+            source: Some(String::from(SOURCE)),
+            message: self.message.clone(),
+            related_information: None,
+            tags: None,
+        }
+    }
+}
+
+fn is_known(code: &str) -> bool {
+    diagnostics::CODES.contains(&code) || lint::ALL.contains(&code)
+}
+
+/// Whether a line assigns the whole `key`, bare or quoted
+fn is_key(line: &str, key: &str) -> bool {
+    let rest = line.trim_start().trim_start_matches(&['"', '\''][..]);
+    if !rest.starts_with(key) {
+        return false;
+    }
+    match rest[key.len()..].chars().next() {
+        Some(ch) => ch.is_whitespace() || ch == '=' || ch == '"' || ch == '\'',
+        None => false,
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let index = LineIndex::new(text, PositionEncoding::Utf8);
+        let config: Config = toml::from_str(text).map_err(|err| {
+            // toml counts columns in bytes; `diagnostic` converts them to the client's encoding
+            let (line, col) = err.line_col().unwrap_or((0, 0));
+            ConfigError {
+                message: format!("invalid {}: {}", FILE_NAME, err),
+                line,
+                col,
+            }
+        })?;
+        let mut codes: Vec<&String> = config.diagnostics.keys().collect();
+        codes.sort();
+        if let Some(code) = codes.into_iter().find(|code| !is_known(code)) {
+            // toml does not keep spans, so look for the key itself
+            let line = (0..index.line_count())
+                .find(|&n| is_key(index.line(n), code))
+                .unwrap_or(0);
+            let col = index.line(line).len() - index.line(line).trim_start().len();
+            return Err(ConfigError {
+                message: format!("unknown diagnostic code '{}'", code),
+                line,
+                col,
+            });
+        }
+        Ok(config)
+    }
+
+    /// Source roots, resolved against the directory of the config file, or
+    /// `workspace` if the config does not list any
+    pub fn source_roots(&self, dir: &Path, workspace: &[PathBuf]) -> Vec<PathBuf> {
+        if self.roots.is_empty() {
+            workspace.to_vec()
+        } else {
+            self.roots.iter().map(|root| dir.join(root)).collect()
+        }
+    }
+
+    /// Drop the diagnostics that are turned off and give the others their configured severity
+    pub fn apply(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        diagnostics
+            .into_iter()
+            .filter_map(|mut diagnostic| {
+                let level = match &diagnostic.code {
+                    Some(NumberOrString::String(code)) => self.diagnostics.get(code),
+                    _ => None,
+                };
+                if let Some(level) = level {
+                    diagnostic.severity = Some(level.severity()?);
+                }
+                Some(diagnostic)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config = Config::parse(
+            "stage = \"PA2\"\nroots = [\"src\"]\n\n[diagnostics]\nunused-variable = \"off\"\n\"argc-mismatch\" = \"warning\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.stage, Stage::Pa2);
+        assert!(config.stage.typeck());
+        assert_eq!(config.roots, vec![PathBuf::from("src")]);
+        assert_eq!(config.diagnostics["unused-variable"], Level::Off);
+        assert_eq!(config.diagnostics["argc-mismatch"], Level::Warning);
+
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.stage, Stage::Pa5);
+        assert!(config.roots.is_empty());
+    }
+
+    #[test]
+    fn unknown_code() {
+        // "unused" only shares a prefix with the key on the line before it
+        let err = Config::parse("[diagnostics]\nunused-variable = \"off\"\n  unused = \"hint\"\n")
+            .unwrap_err();
+        assert_eq!(err.message, "unknown diagnostic code 'unused'");
+        assert_eq!((err.line, err.col), (2, 2));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Config::parse("stage = \"PA6\"\n").is_err());
+        assert!(Config::parse("stages = \"PA2\"\n").is_err());
+        assert!(Config::parse("[diagnostics]\nunused-variable = \"loud\"\n").is_err());
+    }
+
+    #[test]
+    fn multi_byte_error() {
+        // 'é' is 2 bytes but 1 UTF-16 unit
+        let text = "roots = [\"é\"] x\n";
+        let err = Config::parse(text).unwrap_err();
+        assert_eq!((err.line, err.col), (0, 15));
+        let diagnostic = err.diagnostic(&LineIndex::new(text, PositionEncoding::Utf16));
+        assert_eq!(
+            diagnostic.range.start,
+            Position {
+                line: 0,
+                character: 14
+            }
+        );
+        assert_eq!(
+            diagnostic.range.end,
+            Position {
+                line: 0,
+                character: 15
+            }
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let config =
+            Config::parse("[diagnostics]\nunused-variable = \"off\"\nargc-mismatch = \"hint\"\n")
+                .unwrap();
+        let diagnostic = |code: &str| Diagnostic {
+            range: Range {
+                start: Position {
+                    line: 0,
+                    character: 0,
+                },
+                end: Position {
+                    line: 0,
+                    character: 0,
+                },
+            },
+            severity: Some(DiagnosticSeverity::Error),
+            code: Some(NumberOrString::String(String::from(code))),
+            source: None,
+            message: String::new(),
+            related_information: None,
+            tags: None,
+        };
+        let applied = config.apply(vec![
+            diagnostic("unused-variable"),
+            diagnostic("argc-mismatch"),
+            diagnostic("syntax-error"),
+        ]);
+        let severities: Vec<_> = applied.iter().map(|d| d.severity).collect();
+        assert_eq!(
+            severities,
+            vec![
+                Some(DiagnosticSeverity::Hint),
+                Some(DiagnosticSeverity::Error)
+            ]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use common::ErrorKind::{self, *};
+use std::fmt;
+use tower_lsp::lsp_types::*;
+
+/// Name reported as the source of every diagnostic
+pub const SOURCE: &str = "decaf";
+
+/// Message for an error, worded as in the Decaf book
+pub fn message<T: fmt::Debug>(kind: &ErrorKind<'_, T>) -> String {
+    match kind {
+        UnclosedStr(s) => format!("unterminated string constant {}", s),
+        NewlineInStr(s) => format!("illegal newline in string constant {}", s),
+        InvalidEscape => String::from("illegal escape character"),
+        IntTooLarge(s) => format!("integer literal {} is too large", s),
+        UnrecognizedChar(ch) => format!("unrecognized character '{}'", ch),
+        SyntaxError => String::from("syntax error"),
+        ConflictDeclaration { prev, name } => format!(
+            "declaration of '{}' here conflicts with earlier declaration at ({},{})",
+            name, prev.0, prev.1
+        ),
+        NoSuchClass(name) => format!("class '{}' not found", name),
+        CyclicInheritance => String::from("illegal class inheritance (should be acyclic)"),
+        NoMainClass => String::from("no legal Main class named 'Main' was found"),
+        VoidArrayElement => String::from("array element type must be non-void known type"),
+        VoidVar(name) => format!("cannot declare identifier '{}' as void type", name),
+        OverrideVar(name) => format!("overriding variable is not allowed for var '{}'", name),
+        BadOverride { method, parent } => format!(
+            "overriding method '{}' doesn't match the type signature in class '{}'",
+            method, parent
+        ),
+        IncompatibleUnary { op, r } => format!("incompatible operand: {} {:?}", op, r),
+        IncompatibleBinary { l, op, r } => {
+            format!("incompatible operands: {:?} {} {:?}", l, op, r)
+        }
+        TestNotBool => String::from("test expression must have bool type"),
+        BreakOutOfLoop => String::from("'break' is only allowed inside a loop"),
+        UndeclaredVar(name) => format!("undeclared variable '{}'", name),
+        RefInStatic { field, func } => format!(
+            "can not reference a non-static field '{}' from static method '{}'",
+            field, func
+        ),
+        BadFieldAccess { name, owner } => {
+            format!("cannot access field '{}' from '{:?}'", name, owner)
+        }
+        PrivateFieldAccess { name, owner } => {
+            format!("field '{}' of '{:?}' not accessible here", name, owner)
+        }
+        NoSuchField { name, owner } => format!("field '{}' not found in '{:?}'", name, owner),
+        LengthWithArgument(count) => format!(
+            "function 'length' expects 0 argument(s) but {} given",
+            count
+        ),
+        NotFunc { name, owner } => format!("'{}' is not a method in class '{:?}'", name, owner),
+        ArgcMismatch {
+            name,
+            expect,
+            actual,
+        } => format!(
+            "function '{}' expects {} argument(s) but {} given",
+            name, expect, actual
+        ),
+        ArgMismatch { loc, arg, param } => format!(
+            "incompatible argument {}: {:?} given, {:?} expected",
+            loc, arg, param
+        ),
+        ThisInStatic => String::from("can not use this in static function"),
+        NotObject(ty) => format!("{:?} is not a class type", ty),
+        BadPrintArg { loc, ty } => format!(
+            "incompatible argument {}: {:?} given, int/bool/string expected",
+            loc, ty
+        ),
+        ReturnMismatch { expect, actual } => format!(
+            "incompatible return: {:?} given, {:?} expected",
+            actual, expect
+        ),
+        NewArrayNotInt => String::from("new array length must be an integer"),
+        IndexNotArray => String::from("[] can only be applied to arrays"),
+        IndexNotInt => String::from("array subscript must be an integer"),
+        NoReturn => String::from("missing return statement: control reaches end of non-void block"),
+        #[allow(unreachable_patterns)]
+        _ => format!("{:?}", kind),
+    }
+}
+
+/// Declare `CODES` and `code` from one table, so that the list of codes cannot drift
+/// from the match
+macro_rules! codes {
+    ($($kind:pat => $code:literal,)*) => {
+        /// Codes of every parser and type checker error
+        pub const CODES: &[&str] = &[$($code),*];
+
+        /// Stable code of an error kind, used to look up and configure diagnostics
+        pub fn code<T>(kind: &ErrorKind<'_, T>) -> &'static str {
+            match kind {
+                $($kind => $code,)*
+                #[allow(unreachable_patterns)]
+                _ => "error",
+            }
+        }
+    };
+}
+
+codes! {
+    UnclosedStr(_) => "unclosed-string",
+    NewlineInStr(_) => "newline-in-string",
+    InvalidEscape => "invalid-escape",
+    IntTooLarge(_) => "int-too-large",
+    UnrecognizedChar(_) => "unrecognized-char",
+    SyntaxError => "syntax-error",
+    ConflictDeclaration { .. } => "conflict-declaration",
+    NoSuchClass(_) => "no-such-class",
+    CyclicInheritance => "cyclic-inheritance",
+    NoMainClass => "no-main-class",
+    VoidArrayElement => "void-array-element",
+    VoidVar(_) => "void-var",
+    OverrideVar(_) => "override-var",
+    BadOverride { .. } => "bad-override",
+    IncompatibleUnary { .. } => "incompatible-unary",
+    IncompatibleBinary { .. } => "incompatible-binary",
+    TestNotBool => "test-not-bool",
+    BreakOutOfLoop => "break-out-of-loop",
+    UndeclaredVar(_) => "undeclared-var",
+    RefInStatic { .. } => "ref-in-static",
+    BadFieldAccess { .. } => "bad-field-access",
+    PrivateFieldAccess { .. } => "private-field-access",
+    NoSuchField { .. } => "no-such-field",
+    LengthWithArgument(_) => "length-with-argument",
+    NotFunc { .. } => "not-func",
+    ArgcMismatch { .. } => "argc-mismatch",
+    ArgMismatch { .. } => "arg-mismatch",
+    ThisInStatic => "this-in-static",
+    NotObject(_) => "not-object",
+    BadPrintArg { .. } => "bad-print-arg",
+    ReturnMismatch { .. } => "return-mismatch",
+    NewArrayNotInt => "new-array-not-int",
+    IndexNotArray => "index-not-array",
+    IndexNotInt => "index-not-int",
+    NoReturn => "no-return",
+}
+
+/// How much of the code at an error location is underlined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extent {
+    /// The token, e.g. a name or an operator
+    Token,
+    /// The whole expression starting there, e.g. a mistyped argument
+    Expr,
+}
+
+pub fn extent<T>(kind: &ErrorKind<'_, T>) -> Extent {
+    match kind {
+        IncompatibleUnary { .. }
+        | TestNotBool
+        | ArgMismatch { .. }
+        | NotObject(_)
+        | BadPrintArg { .. }
+        | ReturnMismatch { .. }
+        | NewArrayNotInt
+        | IndexNotArray
+        | IndexNotInt => Extent::Expr,
+        _ => Extent::Token,
+    }
+}
+
+/// Every error from the parser or type checker stops compilation
+pub fn severity<T>(_kind: &ErrorKind<'_, T>) -> DiagnosticSeverity {
+    DiagnosticSeverity::Error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Loc;
+
+    #[test]
+    fn messages() {
+        let kind: ErrorKind<()> = UndeclaredVar("x");
+        assert_eq!(message(&kind), "undeclared variable 'x'");
+        assert_eq!(code(&kind), "undeclared-var");
+        let kind: ErrorKind<()> = ConflictDeclaration {
+            prev: Loc(3, 9),
+            name: "x",
+        };
+        assert_eq!(
+            message(&kind),
+            "declaration of 'x' here conflicts with earlier declaration at (3,9)"
+        );
+        let kind: ErrorKind<()> = ArgcMismatch {
+            name: "get",
+            expect: 1,
+            actual: 2,
+        };
+        assert_eq!(
+            message(&kind),
+            "function 'get' expects 1 argument(s) but 2 given"
+        );
+        assert_eq!(code(&kind), "argc-mismatch");
+    }
+
+    #[test]
+    fn codes() {
+        let mut codes = CODES.to_vec();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), CODES.len());
+        assert!(codes
+            .iter()
+            .all(|code| code.chars().all(|ch| ch.is_ascii_lowercase() || ch == '-')));
+    }
+}
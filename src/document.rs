@@ -0,0 +1,235 @@
+use crate::line_index::PositionEncoding;
+use ropey::Rope;
+use std::collections::HashMap;
+use tower_lsp::lsp_types::*;
+
+/// An open text document, kept as a rope so that ranged edits are cheap
+#[derive(Debug, Clone)]
+pub struct Document {
+    rope: Rope,
+    version: Option<i64>,
+}
+
+impl Document {
+    pub fn new(text: &str, version: Option<i64>) -> Document {
+        Document {
+            rope: Rope::from_str(text),
+            version,
+        }
+    }
+
+    pub fn version(&self) -> Option<i64> {
+        self.version
+    }
+
+    pub fn text(&self) -> String {
+        self.rope.to_string()
+    }
+
+    /// Apply one content change, either ranged or a full replacement
+    pub fn apply(&mut self, change: &TextDocumentContentChangeEvent, encoding: PositionEncoding) {
+        match change.range {
+            Some(range) => {
+                let start = self.offset(range.start, encoding);
+                let end = self.offset(range.end, encoding).max(start);
+                self.rope.remove(start..end);
+                self.rope.insert(start, &change.text);
+            }
+            None => {
+                self.rope = Rope::from_str(&change.text);
+            }
+        }
+    }
+
+    /// Convert an LSP position to a char index into the rope,
+    /// clamping positions past the end of a line or of the document
+    fn offset(&self, position: Position, encoding: PositionEncoding) -> usize {
+        let line = position.line as usize;
+        if line >= self.rope.len_lines() {
+            return self.rope.len_chars();
+        }
+        let line_start = self.rope.line_to_char(line);
+        let mut line_end = if line + 1 < self.rope.len_lines() {
+            self.rope.line_to_char(line + 1)
+        } else {
+            self.rope.len_chars()
+        };
+        while line_end > line_start && matches!(self.rope.char(line_end - 1), '\n' | '\r') {
+            line_end -= 1;
+        }
+        match encoding {
+            PositionEncoding::Utf8 => {
+                let line_start_byte = self.rope.char_to_byte(line_start);
+                let line_end_byte = self.rope.char_to_byte(line_end);
+                let byte = (line_start_byte + position.character as usize).min(line_end_byte);
+                self.rope.byte_to_char(byte)
+            }
+            PositionEncoding::Utf16 => {
+                let line_start_cu = self.rope.char_to_utf16_cu(line_start);
+                let line_end_cu = self.rope.char_to_utf16_cu(line_end);
+                let cu = (line_start_cu + position.character as usize).min(line_end_cu);
+                self.rope.utf16_cu_to_char(cu)
+            }
+        }
+    }
+}
+
+/// All documents currently opened by the client
+#[derive(Debug, Default)]
+pub struct DocumentStore {
+    documents: HashMap<Url, Document>,
+}
+
+impl DocumentStore {
+    pub fn open(&mut self, uri: Url, text: &str, version: Option<i64>) -> &Document {
+        self.documents
+            .insert(uri.clone(), Document::new(text, version));
+        &self.documents[&uri]
+    }
+
+    /// Apply changes in the order they were sent, returning the patched document.
+    /// Changes to a document that was never opened are ignored.
+    pub fn change(
+        &mut self,
+        uri: &Url,
+        changes: &[TextDocumentContentChangeEvent],
+        version: Option<i64>,
+        encoding: PositionEncoding,
+    ) -> Option<&Document> {
+        let document = self.documents.get_mut(uri)?;
+        for change in changes.iter() {
+            document.apply(change, encoding);
+        }
+        document.version = version;
+        Some(document)
+    }
+
+    pub fn close(&mut self, uri: &Url) {
+        self.documents.remove(uri);
+    }
+
+    pub fn get(&self, uri: &Url) -> Option<&Document> {
+        self.documents.get(uri)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Url, &Document)> {
+        self.documents.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(
+        range: Option<((u64, u64), (u64, u64))>,
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: range.map(|(start, end)| Range {
+                start: Position {
+                    line: start.0,
+                    character: start.1,
+                },
+                end: Position {
+                    line: end.0,
+                    character: end.1,
+                },
+            }),
+            range_length: None,
+            text: String::from(text),
+        }
+    }
+
+    fn edit(
+        text: &str,
+        change: TextDocumentContentChangeEvent,
+        encoding: PositionEncoding,
+    ) -> String {
+        let mut document = Document::new(text, None);
+        document.apply(&change, encoding);
+        document.text()
+    }
+
+    #[test]
+    fn ranged() {
+        let utf16 = PositionEncoding::Utf16;
+        assert_eq!(
+            edit(
+                "hello world",
+                change(Some(((0, 6), (0, 11))), "there"),
+                utf16
+            ),
+            "hello there"
+        );
+        assert_eq!(
+            edit("ab\ncd", change(Some(((0, 1), (1, 1))), ""), utf16),
+            "ad"
+        );
+        assert_eq!(edit("ab", change(Some(((0, 1), (0, 1))), ""), utf16), "ab");
+    }
+
+    #[test]
+    fn multi_byte() {
+        // '😀' is 2 UTF-16 units and 4 bytes
+        assert_eq!(
+            edit(
+                "a😀b",
+                change(Some(((0, 1), (0, 3))), ""),
+                PositionEncoding::Utf16
+            ),
+            "ab"
+        );
+        assert_eq!(
+            edit(
+                "a😀b",
+                change(Some(((0, 5), (0, 5))), "é"),
+                PositionEncoding::Utf8
+            ),
+            "a😀éb"
+        );
+    }
+
+    #[test]
+    fn clamped() {
+        let utf16 = PositionEncoding::Utf16;
+        assert_eq!(
+            edit("ab\r\ncd", change(Some(((0, 10), (0, 10))), "!"), utf16),
+            "ab!\r\ncd"
+        );
+        assert_eq!(
+            edit("ab\ncd", change(Some(((5, 0), (5, 0))), "!"), utf16),
+            "ab\ncd!"
+        );
+        // an end before the start is an insertion
+        assert_eq!(
+            edit("abc", change(Some(((0, 2), (0, 1))), "!"), utf16),
+            "ab!c"
+        );
+    }
+
+    #[test]
+    fn store() {
+        let uri = Url::parse("file:///Main.decaf").unwrap();
+        let mut store = DocumentStore::default();
+        store.open(uri.clone(), "abc", Some(1));
+        let changes = [
+            change(Some(((0, 0), (0, 0))), "x"),
+            change(Some(((0, 1), (0, 1))), "y"),
+        ];
+        let document = store
+            .change(&uri, &changes, Some(2), PositionEncoding::Utf16)
+            .unwrap();
+        assert_eq!(document.text(), "xyabc");
+        assert_eq!(document.version(), Some(2));
+
+        let changes = [change(None, "class Main {}")];
+        store.change(&uri, &changes, Some(3), PositionEncoding::Utf16);
+        assert_eq!(store.get(&uri).unwrap().text(), "class Main {}");
+
+        store.close(&uri);
+        assert!(store
+            .change(&uri, &changes, Some(4), PositionEncoding::Utf16)
+            .is_none());
+    }
+}
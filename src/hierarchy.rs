@@ -0,0 +1,398 @@
+use crate::analysis::{Analysis, ClassInfo, MemberInfo, Symbol};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tower_lsp::lsp_types::*;
+
+/// A class with the file declaring it
+pub type Class<'a> = (&'a Url, &'a ClassInfo);
+
+/// A symbol with the file declaring it, or `None` if no indexed file declares it.
+/// Locals are keyed by the file they occur in.
+pub type Key = (Option<Url>, Symbol);
+
+/// Whether two classes are the same declaration
+pub fn same(a: Class, b: Class) -> bool {
+    std::ptr::eq(a.1, b.1)
+}
+
+/// The classes of all indexed files, linked by inheritance. Each file is type checked
+/// as a program of its own, so a name refers to the class declared in the same file.
+/// Only classes a file does not declare are looked up by name, in the first file by URI.
+#[derive(Debug)]
+pub struct Hierarchy<'a> {
+    /// Declarations of each name, by URI
+    classes: HashMap<&'a str, Vec<Class<'a>>>,
+    /// Names in order, so that results do not depend on hashing
+    names: Vec<&'a str>,
+}
+
+impl<'a> Hierarchy<'a> {
+    pub fn new(files: &'a [(Url, Arc<Analysis>)]) -> Hierarchy<'a> {
+        let mut sorted: Vec<&(Url, Arc<Analysis>)> = files.iter().collect();
+        sorted.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        let mut classes: HashMap<&str, Vec<Class>> = HashMap::new();
+        for (uri, file) in sorted {
+            for class in file.classes.iter() {
+                let declared = classes.entry(class.name.as_str()).or_default();
+                // a file declaring a class twice does not type check; keep the first
+                if declared.iter().all(|(other, _)| *other != uri) {
+                    declared.push((uri, class));
+                }
+            }
+        }
+        let mut names: Vec<&str> = classes.keys().cloned().collect();
+        names.sort();
+        Hierarchy { classes, names }
+    }
+
+    /// The class `name` as seen from the file `from`
+    pub fn get(&self, from: &Url, name: &str) -> Option<Class<'a>> {
+        let declared = self.classes.get(name)?;
+        declared
+            .iter()
+            .find(|(uri, _)| *uri == from)
+            .or_else(|| declared.first())
+            .cloned()
+    }
+
+    /// The class `name` declared in the file `uri`
+    pub fn declared(&self, uri: &Url, name: &str) -> Option<Class<'a>> {
+        self.declarations(name)
+            .iter()
+            .find(|(other, _)| *other == uri)
+            .cloned()
+    }
+
+    /// Every declaration of a class named `name`, by URI
+    pub fn declarations(&self, name: &str) -> &[Class<'a>] {
+        self.classes.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Every class, by name and then URI
+    pub fn classes(&self) -> impl Iterator<Item = Class<'a>> + '_ {
+        self.names
+            .iter()
+            .flat_map(move |name| self.classes[name].iter().cloned())
+    }
+
+    pub fn parent(&self, (uri, class): Class<'a>) -> Option<Class<'a>> {
+        self.get(uri, class.parent.as_ref()?)
+    }
+
+    /// Ancestors of a class, nearest first, stopping at a cycle
+    pub fn ancestors(&self, class: Class<'a>) -> Vec<Class<'a>> {
+        let mut ancestors: Vec<Class<'a>> = Vec::new();
+        let mut current = self.parent(class);
+        while let Some(parent) = current {
+            if same(parent, class) || ancestors.iter().any(|other| same(*other, parent)) {
+                break;
+            }
+            ancestors.push(parent);
+            current = self.parent(parent);
+        }
+        ancestors
+    }
+
+    /// Classes directly extending `class`
+    pub fn subclasses(&self, class: Class<'a>) -> Vec<Class<'a>> {
+        self.classes()
+            .filter(|subclass| {
+                self.parent(*subclass)
+                    .map_or(false, |parent| same(parent, class))
+            })
+            .collect()
+    }
+
+    /// Classes extending `class` directly or indirectly, nearest first
+    pub fn descendants(&self, class: Class<'a>) -> Vec<Class<'a>> {
+        let mut descendants: Vec<Class<'a>> = Vec::new();
+        let mut i = 0;
+        let mut pending = vec![class];
+        while i < pending.len() {
+            for subclass in self.subclasses(pending[i]) {
+                if !same(subclass, class) && !descendants.iter().any(|other| same(*other, subclass))
+                {
+                    pending.push(subclass);
+                    descendants.push(subclass);
+                }
+            }
+            i += 1;
+        }
+        descendants
+    }
+
+    /// The member `name` as seen from `class`: declared there or inherited from the nearest ancestor
+    pub fn lookup(&self, class: Class<'a>, name: &str) -> Option<(Class<'a>, &'a MemberInfo)> {
+        Some(class)
+            .into_iter()
+            .chain(self.ancestors(class))
+            .find_map(|class| Some((class, class.1.member(name)?)))
+    }
+
+    /// Key of a symbol occurring in the file `uri`. Classes are identified by the file
+    /// declaring them, and members by the class declaring them rather than the class
+    /// they are looked up in.
+    pub fn resolve(&self, uri: &Url, symbol: &Symbol) -> Key {
+        let (class, name, method) = match symbol {
+            Symbol::Local(_) => return (Some(uri.clone()), symbol.clone()),
+            Symbol::Class(name) => {
+                let file = self.get(uri, name).map(|(file, _)| file.clone());
+                return (file, symbol.clone());
+            }
+            Symbol::Field { class, name } => (class, name, false),
+            Symbol::Method { class, name } => (class, name, true),
+        };
+        let class = match self.get(uri, class) {
+            Some(class) => class,
+            None => return (None, symbol.clone()),
+        };
+        match self.lookup(class, name) {
+            Some(((file, owner), member)) if member.method == method => {
+                let class = owner.name.clone();
+                let name = name.clone();
+                let symbol = if method {
+                    Symbol::Method { class, name }
+                } else {
+                    Symbol::Field { class, name }
+                };
+                (Some(file.clone()), symbol)
+            }
+            _ => (Some(class.0.clone()), symbol.clone()),
+        }
+    }
+
+    /// The class declaring the class or member of a key
+    pub fn class(&self, key: &Key) -> Option<Class<'a>> {
+        let name = match &key.1 {
+            Symbol::Class(name) => name,
+            Symbol::Field { class, .. } | Symbol::Method { class, .. } => class,
+            Symbol::Local(_) => return None,
+        };
+        self.declared(key.0.as_ref()?, name)
+    }
+
+    /// Classes whose `method` may run for a call resolved to `method` of `class`:
+    /// the class itself and the subclasses overriding it, unless the method is static
+    pub fn dispatch(&self, class: Class<'a>, method: &str) -> Vec<Class<'a>> {
+        if class.1.member(method).map_or(false, |m| m.is_static) {
+            return vec![class];
+        }
+        let below = self.descendants(class).into_iter();
+        Some(class)
+            .into_iter()
+            .chain(below.filter(|(_, class)| class.member(method).map_or(false, |m| m.method)))
+            .collect()
+    }
+
+    /// Classes declaring a method that shares its slot with `method` of `class`:
+    /// the topmost declaration and every override below it
+    pub fn overrides(&self, class: Class<'a>, method: &str) -> Vec<Class<'a>> {
+        let declares = |(_, class): &Class| class.member(method).map_or(false, |m| m.method);
+        let root = Some(class)
+            .into_iter()
+            .chain(self.ancestors(class))
+            .filter(|class| declares(class))
+            .last()
+            .unwrap_or(class);
+        Some(root)
+            .into_iter()
+            .chain(self.descendants(root))
+            .filter(|class| declares(class))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::tests::{analyze, find};
+
+    /// Each file is a program of its own, with its own `Main` and `Shape`
+    const PROGRAM: &str = "class Main {
+    static void main() {
+        class Shape shape = new Shape();
+        Print(shape.area());
+    }
+}
+
+class Shape {
+    int area() {
+        return 1;
+    }
+}
+";
+
+    const SHAPES: &str = "class Square extends Shape {
+    int area() {
+        return 2;
+    }
+}
+
+class Circle extends Shape {
+}
+";
+
+    fn files(sources: &[(&str, &str)]) -> Vec<(Url, Arc<Analysis>)> {
+        sources
+            .iter()
+            .map(|(uri, text)| (Url::parse(uri).unwrap(), Arc::new(analyze(uri, text))))
+            .collect()
+    }
+
+    fn workspace() -> Vec<(Url, Arc<Analysis>)> {
+        files(&[
+            ("file:///b.decaf", PROGRAM),
+            ("file:///a.decaf", PROGRAM),
+            ("file:///shapes.decaf", SHAPES),
+        ])
+    }
+
+    fn class(name: &str) -> Symbol {
+        Symbol::Class(String::from(name))
+    }
+
+    fn method(class: &str, name: &str) -> Symbol {
+        Symbol::Method {
+            class: String::from(class),
+            name: String::from(name),
+        }
+    }
+
+    #[test]
+    fn classes_by_file() {
+        let entries = workspace();
+        let hierarchy = Hierarchy::new(&entries);
+        let (b, a, shapes) = (&entries[0].0, &entries[1].0, &entries[2].0);
+        assert_eq!(
+            hierarchy.resolve(a, &class("Shape")),
+            (Some(a.clone()), class("Shape"))
+        );
+        assert_eq!(
+            hierarchy.resolve(b, &class("Shape")),
+            (Some(b.clone()), class("Shape"))
+        );
+        // a class the file does not declare is looked up in the first file by URI
+        assert_eq!(
+            hierarchy.resolve(shapes, &class("Shape")),
+            (Some(a.clone()), class("Shape"))
+        );
+        assert_eq!(hierarchy.resolve(a, &class("Nope")), (None, class("Nope")));
+        assert_eq!(hierarchy.declarations("Shape").len(), 2);
+        let names: Vec<(&str, &str)> = hierarchy
+            .classes()
+            .map(|(uri, class)| (class.name.as_str(), uri.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("Circle", "file:///shapes.decaf"),
+                ("Main", "file:///a.decaf"),
+                ("Main", "file:///b.decaf"),
+                ("Shape", "file:///a.decaf"),
+                ("Shape", "file:///b.decaf"),
+                ("Square", "file:///shapes.decaf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn members_by_declaring_class() {
+        let entries = workspace();
+        let hierarchy = Hierarchy::new(&entries);
+        let (a, shapes) = (&entries[1].0, &entries[2].0);
+        assert_eq!(
+            hierarchy.resolve(shapes, &method("Circle", "area")),
+            (Some(a.clone()), method("Shape", "area"))
+        );
+        assert_eq!(
+            hierarchy.resolve(shapes, &method("Square", "area")),
+            (Some(shapes.clone()), method("Square", "area"))
+        );
+        let declaration = Range {
+            start: Position {
+                line: 2,
+                character: 20,
+            },
+            end: Position {
+                line: 2,
+                character: 25,
+            },
+        };
+        let local = Symbol::Local(declaration);
+        assert_eq!(hierarchy.resolve(a, &local), (Some(a.clone()), local));
+    }
+
+    #[test]
+    fn references() {
+        let entries = workspace();
+        let hierarchy = Hierarchy::new(&entries);
+        let key = hierarchy.resolve(&entries[1].0, &class("Shape"));
+        let references = |(uri, file): &(Url, Arc<Analysis>)| -> Vec<Range> {
+            let mut ranges: Vec<Range> = file
+                .occurrences
+                .iter()
+                .filter(|(_, occurrence)| hierarchy.resolve(uri, &occurrence.symbol) == key)
+                .map(|(range, _)| *range)
+                .collect();
+            ranges.sort_by_key(|range| range.start);
+            ranges
+        };
+        let a = &entries[1].1;
+        assert_eq!(
+            references(&entries[1]),
+            vec![
+                find(a, "Shape", 0),
+                find(a, "Shape", 1),
+                find(a, "Shape", 2)
+            ]
+        );
+        // `b.decaf` declares a `Shape` of its own
+        assert_eq!(references(&entries[0]), Vec::new());
+        let shapes = &entries[2].1;
+        assert_eq!(
+            references(&entries[2]),
+            vec![find(shapes, "Shape", 0), find(shapes, "Shape", 1)]
+        );
+    }
+
+    fn names(classes: Vec<Class>) -> Vec<(&str, &str)> {
+        classes
+            .into_iter()
+            .map(|(uri, class)| (class.name.as_str(), uri.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn implementations() {
+        let entries = workspace();
+        let hierarchy = Hierarchy::new(&entries);
+        let (b, a, shapes) = (&entries[0].0, &entries[1].0, &entries[2].0);
+        let shape = hierarchy.declared(a, "Shape").unwrap();
+        assert_eq!(
+            names(hierarchy.descendants(shape)),
+            vec![
+                ("Circle", "file:///shapes.decaf"),
+                ("Square", "file:///shapes.decaf")
+            ]
+        );
+        // `shapes.decaf` extends the `Shape` of `a.decaf`
+        let other = hierarchy.declared(b, "Shape").unwrap();
+        assert_eq!(names(hierarchy.descendants(other)), Vec::new());
+        assert_eq!(
+            names(hierarchy.dispatch(shape, "area")),
+            vec![
+                ("Shape", "file:///a.decaf"),
+                ("Square", "file:///shapes.decaf")
+            ]
+        );
+        let square = hierarchy.declared(shapes, "Square").unwrap();
+        assert_eq!(
+            names(hierarchy.overrides(square, "area")),
+            names(hierarchy.dispatch(shape, "area"))
+        );
+        assert_eq!(
+            names(hierarchy.ancestors(square)),
+            vec![("Shape", "file:///a.decaf")]
+        );
+    }
+}
@@ -1,51 +1,60 @@
+pub mod analysis;
+pub mod calls;
+pub mod classes;
+pub mod config;
+pub mod diagnostics;
+pub mod document;
+pub mod hierarchy;
+pub mod line_index;
+pub mod lint;
+pub mod protocol;
+pub mod recovery;
+pub mod rename;
+pub mod router;
+pub mod shift;
+pub mod span_index;
+pub mod suppress;
+pub mod tokens;
+pub mod workspace;
+
 use common;
+use line_index::LineIndex;
 use syntax;
 use tower_lsp::lsp_types::*;
 
-pub fn pos(loc: &common::Loc) -> Position {
-    if loc.0 == 0 || loc.1 == 0 {
-        Position {
-            line: 0,
-            character: 0,
-        }
-    } else {
-        Position {
-            line: loc.0 as u64 - 1,
-            character: loc.1 as u64 - 1,
-        }
-    }
+pub fn pos(index: &LineIndex, loc: &common::Loc) -> Position {
+    index.loc(loc)
 }
 
-pub fn range(loc: &common::Loc) -> Range {
+pub fn range(index: &LineIndex, loc: &common::Loc) -> Range {
     Range {
-        start: pos(loc),
-        end: pos(loc),
+        start: pos(index, loc),
+        end: pos(index, loc),
     }
 }
 
-pub fn range_name(loc: &common::Loc, name: &str) -> Range {
+pub fn range_name(index: &LineIndex, loc: &common::Loc, name: &str) -> Range {
     Range {
-        start: pos(loc),
-        end: pos(&common::Loc(loc.0, loc.1 + name.as_bytes().len() as u32)),
+        start: pos(index, loc),
+        end: pos(
+            index,
+            &common::Loc(loc.0, loc.1 + name.as_bytes().len() as u32),
+        ),
     }
 }
 
-pub fn range2(loc: &common::Loc, end: &common::Loc) -> Range {
+pub fn range2(index: &LineIndex, loc: &common::Loc, end: &common::Loc) -> Range {
     Range {
-        start: pos(loc),
-        end: pos(end),
+        start: pos(index, loc),
+        end: pos(index, end),
     }
 }
 
-pub fn token(token: &syntax::parser::Token) -> Range {
+pub fn token(index: &LineIndex, token: &syntax::parser::Token) -> Range {
+    let line = token.line as usize - 1;
+    let col = token.col as usize - 1;
     Range {
-        start: Position {
-            line: token.line as u64 - 1,
-            character: token.col as u64 - 1,
-        },
-        end: Position {
-            line: token.line as u64 - 1,
-            character: (token.col as u64 + token.piece.len() as u64) - 1 - 1,
-        },
+        start: index.position(line, col),
+        end: index.position(line, col + token.piece.len()),
     }
 }
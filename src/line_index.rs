@@ -0,0 +1,242 @@
+use common::Loc;
+use tower_lsp::lsp_types::Position;
+
+/// Unit in which `Position.character` is counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    Utf16,
+}
+
+impl Default for PositionEncoding {
+    fn default() -> PositionEncoding {
+        PositionEncoding::Utf16
+    }
+}
+
+impl PositionEncoding {
+    /// Pick an encoding from those offered by the client. UTF-8 is preferred since
+    /// it matches the byte columns produced by the lexer; UTF-16 is mandatory.
+    pub fn negotiate<'a>(offered: impl IntoIterator<Item = &'a str>) -> PositionEncoding {
+        if offered.into_iter().any(|name| name == "utf-8") {
+            PositionEncoding::Utf8
+        } else {
+            PositionEncoding::Utf16
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PositionEncoding::Utf8 => "utf-8",
+            PositionEncoding::Utf16 => "utf-16",
+        }
+    }
+}
+
+/// Converts between lexer locations (1-based line, 1-based byte column) and LSP positions
+#[derive(Debug, Clone, Default)]
+pub struct LineIndex {
+    text: String,
+    line_starts: Vec<usize>,
+    encoding: PositionEncoding,
+}
+
+impl LineIndex {
+    pub fn new(text: &str, encoding: PositionEncoding) -> LineIndex {
+        let mut line_starts = vec![0];
+        for (i, b) in text.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        LineIndex {
+            text: String::from(text),
+            line_starts,
+            encoding,
+        }
+    }
+
+    pub fn encoding(&self) -> PositionEncoding {
+        self.encoding
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Text of a 0-based line, without its line terminator
+    pub fn line(&self, line: usize) -> &str {
+        let start = match self.line_starts.get(line) {
+            Some(start) => *start,
+            None => return "",
+        };
+        let end = match self.line_starts.get(line + 1) {
+            Some(next) => *next - 1,
+            None => self.text.len(),
+        };
+        self.text[start..end].trim_end_matches('\r')
+    }
+
+    /// Position of a 0-based byte column in a 0-based line
+    pub fn position(&self, line: usize, col: usize) -> Position {
+        let text = self.line(line);
+        let mut col = col.min(text.len());
+        while !text.is_char_boundary(col) {
+            col -= 1;
+        }
+        let character = match self.encoding {
+            PositionEncoding::Utf8 => col,
+            PositionEncoding::Utf16 => text[..col].encode_utf16().count(),
+        };
+        Position {
+            line: line as u64,
+            character: character as u64,
+        }
+    }
+
+    /// Position of a lexer location; the dummy location maps to the start of file
+    pub fn loc(&self, loc: &Loc) -> Position {
+        if loc.0 == 0 || loc.1 == 0 {
+            Position {
+                line: 0,
+                character: 0,
+            }
+        } else {
+            self.position(loc.0 as usize - 1, loc.1 as usize - 1)
+        }
+    }
+
+    /// 0-based byte column of a position within its line
+    pub fn col(&self, position: Position) -> usize {
+        let text = self.line(position.line as usize);
+        let character = position.character as usize;
+        match self.encoding {
+            PositionEncoding::Utf8 => {
+                let mut col = character.min(text.len());
+                while !text.is_char_boundary(col) {
+                    col -= 1;
+                }
+                col
+            }
+            PositionEncoding::Utf16 => {
+                let mut units = 0;
+                for (col, ch) in text.char_indices() {
+                    if units + ch.len_utf16() > character {
+                        return col;
+                    }
+                    units += ch.len_utf16();
+                }
+                text.len()
+            }
+        }
+    }
+
+    /// Lexer location of a position
+    pub fn to_loc(&self, position: Position) -> Loc {
+        Loc(position.line as u32 + 1, self.col(position) as u32 + 1)
+    }
+
+    /// Byte offset of a lexer location into the whole text
+    pub fn loc_offset(&self, loc: &Loc) -> usize {
+        let start = match self.line_starts.get((loc.0 as usize).saturating_sub(1)) {
+            Some(start) => *start,
+            None => return self.text.len(),
+        };
+        (start + (loc.1 as usize).saturating_sub(1)).min(self.text.len())
+    }
+
+    /// Position of a byte offset into the whole text
+    pub fn offset_position(&self, offset: usize) -> Position {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        self.position(line, offset - self.line_starts[line])
+    }
+
+    /// Byte offset of a position into the whole text
+    pub fn offset(&self, position: Position) -> usize {
+        match self.line_starts.get(position.line as usize) {
+            Some(start) => start + self.col(position),
+            None => self.text.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(line: u64, character: u64) -> Position {
+        Position { line, character }
+    }
+
+    // 'é' is 2 bytes and 1 UTF-16 unit, '😀' is 4 bytes and 2 units
+    const TEXT: &str = "aé😀b\r\nx";
+
+    #[test]
+    fn utf16_columns() {
+        let index = LineIndex::new(TEXT, PositionEncoding::Utf16);
+        assert_eq!(index.position(0, 7), pos(0, 4));
+        assert_eq!(index.col(pos(0, 4)), 7);
+        // inside a surrogate pair or a character, round down to its start
+        assert_eq!(index.col(pos(0, 3)), 3);
+        assert_eq!(index.position(0, 2), pos(0, 1));
+    }
+
+    #[test]
+    fn utf8_columns() {
+        let index = LineIndex::new(TEXT, PositionEncoding::Utf8);
+        assert_eq!(index.position(0, 7), pos(0, 7));
+        assert_eq!(index.position(0, 2), pos(0, 1));
+        assert_eq!(index.col(pos(0, 5)), 3);
+    }
+
+    #[test]
+    fn line_ends() {
+        let index = LineIndex::new(TEXT, PositionEncoding::Utf16);
+        assert_eq!(index.line_count(), 2);
+        assert_eq!(index.line(0), "aé😀b");
+        assert_eq!(index.line(2), "");
+        // columns past the end clamp before the "\r\n"
+        assert_eq!(index.position(0, 100), pos(0, 5));
+        assert_eq!(index.col(pos(0, 100)), 8);
+    }
+
+    #[test]
+    fn offsets() {
+        let index = LineIndex::new(TEXT, PositionEncoding::Utf16);
+        assert_eq!(index.offset_position(10), pos(1, 0));
+        assert_eq!(index.offset(pos(1, 0)), 10);
+        assert_eq!(index.offset(pos(0, 4)), 7);
+        assert_eq!(index.offset(pos(5, 0)), TEXT.len());
+        assert_eq!(index.offset_position(TEXT.len()), pos(1, 1));
+    }
+
+    #[test]
+    fn locations() {
+        let index = LineIndex::new(TEXT, PositionEncoding::Utf16);
+        assert_eq!(index.loc(&Loc(1, 8)), pos(0, 4));
+        assert_eq!(index.loc(&Loc(0, 0)), pos(0, 0));
+        assert_eq!(index.to_loc(pos(0, 4)), Loc(1, 8));
+        assert_eq!(index.loc_offset(&Loc(2, 1)), 10);
+        assert_eq!(index.loc_offset(&Loc(9, 1)), TEXT.len());
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(
+            PositionEncoding::negotiate(vec!["utf-16", "utf-8"]),
+            PositionEncoding::Utf8
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(vec!["utf-32"]),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(PositionEncoding::negotiate(None), PositionEncoding::Utf16);
+    }
+}
@@ -0,0 +1,338 @@
+use crate::diagnostics::SOURCE;
+use crate::line_index::LineIndex;
+use crate::tokens::{self, Tok};
+use common::Loc;
+use std::collections::HashSet;
+use tower_lsp::lsp_types::*;
+
+pub const UNUSED_VARIABLE: &str = "unused-variable";
+pub const UNUSED_PARAMETER: &str = "unused-parameter";
+pub const UNREACHABLE_CODE: &str = "unreachable-code";
+pub const SHADOWED_FIELD: &str = "shadowed-field";
+pub const SELF_ASSIGNMENT: &str = "self-assignment";
+pub const EMPTY_BODY: &str = "empty-body";
+/// Reported for a `decaf-ignore` comment that suppresses nothing
+pub const UNUSED_SUPPRESSION: &str = "unused-suppression";
+
+/// Codes of every lint, in the order they are documented
+pub const ALL: &[&str] = &[
+    UNUSED_VARIABLE,
+    UNUSED_PARAMETER,
+    UNREACHABLE_CODE,
+    SHADOWED_FIELD,
+    SELF_ASSIGNMENT,
+    EMPTY_BODY,
+    UNUSED_SUPPRESSION,
+];
+
+#[derive(Debug)]
+struct Local {
+    loc: (u32, u32),
+    name: String,
+    range: Range,
+    param: bool,
+}
+
+/// A lint finding, turned into a diagnostic once the file is walked
+#[derive(Debug)]
+struct Warning {
+    code: &'static str,
+    range: Range,
+    message: String,
+    unnecessary: bool,
+    related: Option<(Range, String)>,
+}
+
+/// Warnings collected while walking the AST of one file
+#[derive(Debug, Default)]
+pub struct Linter {
+    tokens: Vec<Tok>,
+    /// Fields of the class being walked and its ancestors, with their declarations
+    fields: Vec<(String, Range)>,
+    /// Locals and parameters of the method being walked
+    locals: Vec<Local>,
+    used: HashSet<(u32, u32)>,
+    warnings: Vec<Warning>,
+}
+
+impl Linter {
+    pub fn new(tokens: Vec<Tok>) -> Linter {
+        Linter {
+            tokens,
+            ..Linter::default()
+        }
+    }
+
+    fn warn(&mut self, code: &'static str, range: Range, message: String, unnecessary: bool) {
+        self.warnings.push(Warning {
+            code,
+            range,
+            message,
+            unnecessary,
+            related: None,
+        });
+    }
+
+    /// Range from the start of the statement at `first` to the end of the one at `last`
+    fn statements(&self, index: &LineIndex, first: &Loc, last: &Loc) -> Range {
+        let (start, _) = tokens::stmt_span(&self.tokens, index.loc_offset(first));
+        let (_, end) = tokens::stmt_span(&self.tokens, index.loc_offset(last));
+        Range {
+            start: index.offset_position(start),
+            end: index.offset_position(end),
+        }
+    }
+
+    pub fn enter_class(&mut self, fields: Vec<(String, Range)>) {
+        self.fields = fields;
+    }
+
+    pub fn declare_local(&mut self, loc: &Loc, name: &str, range: Range) {
+        if let Some((_, field)) = self.fields.iter().find(|(field, _)| field == name) {
+            self.warnings.push(Warning {
+                code: SHADOWED_FIELD,
+                range,
+                message: format!("local variable '{}' shadows a field", name),
+                unnecessary: false,
+                related: Some((*field, format!("field '{}' is declared here", name))),
+            });
+        }
+        self.locals.push(Local {
+            loc: (loc.0, loc.1),
+            name: String::from(name),
+            range,
+            param: false,
+        });
+    }
+
+    pub fn declare_param(&mut self, loc: &Loc, name: &str, range: Range) {
+        self.locals.push(Local {
+            loc: (loc.0, loc.1),
+            name: String::from(name),
+            range,
+            param: true,
+        });
+    }
+
+    /// Record a read of the variable declared at `decl`; assignments do not count
+    pub fn use_var(&mut self, decl: &Loc) {
+        self.used.insert((decl.0, decl.1));
+    }
+
+    /// Report the locals and parameters of the method just walked that were never used
+    pub fn leave_func(&mut self) {
+        let locals = std::mem::take(&mut self.locals);
+        for local in locals {
+            if self.used.contains(&local.loc) || local.name.starts_with('_') {
+                continue;
+            }
+            let (code, what) = if local.param {
+                (UNUSED_PARAMETER, "parameter")
+            } else {
+                (UNUSED_VARIABLE, "variable")
+            };
+            self.warn(
+                code,
+                local.range,
+                format!("unused {} '{}'", what, local.name),
+                true,
+            );
+        }
+    }
+
+    /// Statements from `first` to `last` follow a `return` or `break`
+    pub fn unreachable(&mut self, index: &LineIndex, first: &Loc, last: &Loc) {
+        let range = self.statements(index, first, last);
+        self.warn(
+            UNREACHABLE_CODE,
+            range,
+            String::from("unreachable code"),
+            true,
+        );
+    }
+
+    /// An assignment starting at `start`, with its `=` at `stmt`, assigns `name` to itself
+    pub fn self_assignment(&mut self, index: &LineIndex, start: &Loc, stmt: &Loc, name: &str) {
+        let range = self.statements(index, start, stmt);
+        self.warn(
+            SELF_ASSIGNMENT,
+            range,
+            format!("'{}' is assigned to itself", name),
+            true,
+        );
+    }
+
+    pub fn empty_body(&mut self, index: &LineIndex, stmt: &Loc, keyword: &str) {
+        let (start, end) = tokens::token_span(&self.tokens, index.loc_offset(stmt));
+        let range = Range {
+            start: index.offset_position(start),
+            end: index.offset_position(end),
+        };
+        self.warn(
+            EMPTY_BODY,
+            range,
+            format!("'{}' statement has an empty body", keyword),
+            false,
+        );
+    }
+
+    pub fn finish(self, uri: &Url) -> Vec<Diagnostic> {
+        self.warnings
+            .into_iter()
+            .map(|warning| Diagnostic {
+                range: warning.range,
+                severity: Some(if warning.code == UNUSED_PARAMETER {
+                    DiagnosticSeverity::Hint
+                } else {
+                    DiagnosticSeverity::Warning
+                }),
+                code: Some(NumberOrString::String(String::from(warning.code))),
+                source: Some(String::from(SOURCE)),
+                message: warning.message,
+                related_information: warning.related.map(|(range, message)| {
+                    vec![DiagnosticRelatedInformation {
+                        location: Location {
+                            uri: uri.clone(),
+                            range,
+                        },
+                        message,
+                    }]
+                }),
+                tags: if warning.unnecessary {
+                    Some(vec![DiagnosticTag::Unnecessary])
+                } else {
+                    None
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::tests::{analyze, diagnostic, find};
+    use crate::analysis::Analysis;
+
+    /// Ranges of the diagnostics with `code`
+    fn ranges(file: &Analysis, code: &str) -> Vec<Range> {
+        let code = NumberOrString::String(String::from(code));
+        file.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.code.as_ref() == Some(&code))
+            .map(|diagnostic| diagnostic.range)
+            .collect()
+    }
+
+    #[test]
+    fn clean() {
+        let file = analyze(
+            "file:///fibonacci.decaf",
+            include_str!("../fibonacci.decaf"),
+        );
+        assert_eq!(file.diagnostics, Vec::new());
+    }
+
+    #[test]
+    fn unused() {
+        let text = "class Main {
+    static void main() {
+        int first;
+        int second;
+        second = 1;
+        int third = 2;
+        Print(third);
+    }
+
+    static int twice(int n, int base) {
+        return n + n;
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        // assigning a variable does not use it
+        assert_eq!(
+            ranges(&file, UNUSED_VARIABLE),
+            vec![find(&file, "first", 0), find(&file, "second", 0)]
+        );
+        let parameter = diagnostic(&file, UNUSED_PARAMETER);
+        assert_eq!(parameter.range, find(&file, "base", 0));
+        assert_eq!(parameter.message, "unused parameter 'base'");
+        assert_eq!(parameter.severity, Some(DiagnosticSeverity::Hint));
+        assert_eq!(parameter.tags, Some(vec![DiagnosticTag::Unnecessary]));
+    }
+
+    #[test]
+    fn overrides() {
+        let text = "class Main {
+    static void main() {
+    }
+}
+
+class A {
+    int get(int index) {
+        return 1;
+    }
+
+    int other(int index) {
+        return 1;
+    }
+}
+
+class B extends A {
+    int get(int index) {
+        return 2;
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        // the parameters of `get` are fixed by the signature it shares
+        assert_eq!(
+            ranges(&file, UNUSED_PARAMETER),
+            vec![find(&file, "index", 1)]
+        );
+    }
+
+    #[test]
+    fn statements() {
+        let text = "class Main {
+    int size;
+
+    static void main() {
+    }
+
+    int get(int n) {
+        int size = n;
+        n = n;
+        if (size > 0) {
+        }
+        while (false) {
+        }
+        return size;
+        Print(1);
+        Print(2);
+    }
+}
+";
+        let file = analyze("file:///main.decaf", text);
+        let shadowed = diagnostic(&file, SHADOWED_FIELD);
+        assert_eq!(shadowed.range, find(&file, "size", 1));
+        assert_eq!(shadowed.message, "local variable 'size' shadows a field");
+        let related = shadowed.related_information.as_ref().unwrap();
+        assert_eq!(related[0].location.range, find(&file, "size", 0));
+        assert_eq!(
+            ranges(&file, SELF_ASSIGNMENT),
+            vec![find(&file, "n = n;", 0)]
+        );
+        assert_eq!(
+            ranges(&file, EMPTY_BODY),
+            vec![find(&file, "if", 0), find(&file, "while", 0)]
+        );
+        let unreachable = diagnostic(&file, UNREACHABLE_CODE);
+        assert_eq!(
+            unreachable.range,
+            find(&file, "Print(1);\n        Print(2);", 0)
+        );
+        assert_eq!(unreachable.tags, Some(vec![DiagnosticTag::Unnecessary]));
+    }
+}
@@ -1,372 +1,481 @@
 use common::Loc;
-use decaf_lsp::*;
-use jsonrpc_core::Result;
+use decaf_lsp::analysis::{Analysis, Analyzer, Symbol};
+use decaf_lsp::calls;
+use decaf_lsp::classes;
+use decaf_lsp::config::{self, Config};
+use decaf_lsp::document::DocumentStore;
+use decaf_lsp::hierarchy::{Hierarchy, Key};
+use decaf_lsp::line_index::LineIndex;
+use decaf_lsp::line_index::PositionEncoding;
+use decaf_lsp::protocol::{CallHierarchyCallsParams, TypeHierarchyParams};
+use decaf_lsp::rename;
+use decaf_lsp::router::{Extension, Router};
+use decaf_lsp::workspace;
+use jsonrpc_core::{Error, Result};
 use log::*;
-use serde_json::Value;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
-use syntax::{self, *};
-use tokio;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::{self, task, time};
 use tower_lsp::lsp_types::request::*;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{LanguageServer, LspService, Printer, Server};
-use typeck;
 
+/// How long to wait for further edits before analysing a changed document
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Methods answered by `Extensions`
+const EXTENSIONS: &[&str] = &[
+    "textDocument/prepareCallHierarchy",
+    "callHierarchy/incomingCalls",
+    "callHierarchy/outgoingCalls",
+    "textDocument/prepareTypeHierarchy",
+    "typeHierarchy/supertypes",
+    "typeHierarchy/subtypes",
+    "decaf/classHierarchy",
+];
+
+/// Latest analysis of every known file. Snapshots are swapped in whole,
+/// so readers only hold the lock long enough to clone an `Arc`.
 #[derive(Debug, Default)]
 struct State {
-    files: HashMap<Url, FileState>,
+    files: RwLock<HashMap<Url, Arc<Analysis>>>,
 }
 
-#[derive(Debug, Default)]
-struct FileState {
-    content: String,
-    symbols: Vec<SymbolInformation>,
-    hovers: Vec<(Range, Hover)>,
-    ranges: Vec<FoldingRange>,
-    definitions: Vec<(Range, Range)>, // ref, def
+#[derive(Debug, Default, Clone)]
+struct Backend {
+    state: Arc<State>,
+    documents: Arc<Mutex<DocumentStore>>,
+    encoding: Arc<RwLock<PositionEncoding>>,
+    roots: Arc<Mutex<Vec<PathBuf>>>,
+    config: Arc<RwLock<Arc<Config>>>,
+    watch_files: Arc<AtomicBool>,
+    /// Whether the client takes document symbols as a tree
+    hierarchical_symbols: Arc<AtomicBool>,
+    scheduler: Scheduler,
 }
 
-#[derive(Debug, Default)]
-struct Backend {
-    state: Arc<Mutex<State>>,
+/// Tracks the latest requested analysis of each file so that obsolete runs are dropped
+#[derive(Debug, Default, Clone)]
+struct Scheduler {
+    generations: Arc<Mutex<HashMap<Url, u64>>>,
 }
 
-impl State {
-    fn get_file(&mut self, uri: &Url) -> &mut FileState {
-        match self.files.entry(uri.clone()) {
-            Entry::Occupied(o) => o.into_mut(),
-            Entry::Vacant(v) => v.insert(FileState::default()),
-        }
+impl Scheduler {
+    /// Start a new generation for `uri`, cancelling any run in flight
+    fn next(&self, uri: &Url) -> u64 {
+        let mut generations = self.generations.lock().unwrap();
+        let generation = generations.entry(uri.clone()).or_insert(0);
+        *generation += 1;
+        *generation
+    }
+
+    fn is_latest(&self, uri: &Url, generation: u64) -> bool {
+        self.generations.lock().unwrap().get(uri) == Some(&generation)
     }
 }
 
-impl Backend {
-    fn expr<'a>(&self, expr: &Expr<'a>, state: &mut FileState) {
-        match &expr.kind {
-            ExprKind::VarSel(varsel) => {
-                self.varsel(&expr.loc, varsel, state);
-            }
-            ExprKind::IndexSel(indexsel) => {
-                self.expr(&indexsel.arr, state);
-                self.expr(&indexsel.idx, state);
-            }
-            ExprKind::Call(call) => {
-                self.expr(&call.func, state);
-                for arg in call.arg.iter() {
-                    self.expr(&arg, state);
-                }
-            }
-            ExprKind::Unary(un) => {
-                self.expr(&un.r, state);
-            }
-            ExprKind::Binary(bin) => {
-                self.expr(&bin.l, state);
-                self.expr(&bin.r, state);
+impl State {
+    fn get_file(&self, uri: &Url) -> Option<Arc<Analysis>> {
+        self.files.read().unwrap().get(uri).cloned()
+    }
+
+    /// The file, unless `position` is in code its stale model knows nothing about
+    fn file_at(&self, uri: &Url, position: Position) -> Option<Arc<Analysis>> {
+        self.get_file(uri).filter(|file| file.known(position))
+    }
+
+    fn all_files(&self) -> Vec<Arc<Analysis>> {
+        self.files.read().unwrap().values().cloned().collect()
+    }
+
+    fn entries(&self) -> Vec<(Url, Arc<Analysis>)> {
+        let files = self.files.read().unwrap();
+        files
+            .iter()
+            .map(|(uri, file)| (uri.clone(), file.clone()))
+            .collect()
+    }
+
+    /// Where a class or member occurring in the file `uri` is declared, searching all files
+    fn declaration(&self, uri: &Url, symbol: &Symbol) -> Option<Location> {
+        let entries = self.entries();
+        let hierarchy = Hierarchy::new(&entries);
+        let key = hierarchy.resolve(uri, symbol);
+        let (uri, class) = hierarchy.class(&key)?;
+        let range = match &key.1 {
+            Symbol::Field { name, .. } | Symbol::Method { name, .. } => {
+                class.member(name)?.selection
             }
-            _ => {}
-        }
+            _ => class.selection,
+        };
+        Some(Location {
+            uri: uri.clone(),
+            range,
+        })
     }
 
-    fn varsel<'a>(&self, loc: &Loc, varsel: &VarSel<'a>, state: &mut FileState) {
-        state.hovers.push((
-            range_name(loc, varsel.name),
-            Hover {
-                contents: HoverContents::Scalar(MarkedString::from_markdown(format!(
-                    "{}: {:?}",
-                    varsel.name,
-                    varsel.ty.get(),
-                ))),
-                range: Some(range(&loc)),
-            },
-        ));
-        if let Some(expr) = &varsel.owner {
-            self.expr(&expr, state);
+    /// Subclasses of a class, or the methods overriding a method, in all files
+    fn implementations(&self, uri: &Url, symbol: &Symbol) -> Vec<Location> {
+        let entries = self.entries();
+        let hierarchy = Hierarchy::new(&entries);
+        let key = hierarchy.resolve(uri, symbol);
+        let method = match &key.1 {
+            Symbol::Class(_) => None,
+            Symbol::Method { name, .. } => Some(name),
+            _ => return Vec::new(),
+        };
+        let class = match hierarchy.class(&key) {
+            Some(class) => class,
+            None => return Vec::new(),
+        };
+        hierarchy
+            .descendants(class)
+            .into_iter()
+            .filter_map(|(uri, class)| {
+                let range = match method {
+                    Some(method) => class.member(method).filter(|m| m.method)?.selection,
+                    None => class.selection,
+                };
+                Some(Location {
+                    uri: uri.clone(),
+                    range,
+                })
+            })
+            .collect()
+    }
+
+    /// Every occurrence of `symbol`, which occurs in the file `uri`
+    fn occurrences(&self, uri: &Url, symbol: &Symbol, declarations: bool) -> Vec<Location> {
+        let entries = self.entries();
+        let hierarchy = Hierarchy::new(&entries);
+        let key = hierarchy.resolve(uri, symbol);
+        locations(&entries, &hierarchy, &[key], declarations)
+    }
+}
+
+/// Every occurrence of the symbols with the given keys
+fn locations(
+    entries: &[(Url, Arc<Analysis>)],
+    hierarchy: &Hierarchy,
+    keys: &[Key],
+    declarations: bool,
+) -> Vec<Location> {
+    let mut locations = Vec::new();
+    for (uri, file) in entries.iter() {
+        // locals only occur in their own file
+        let wanted = keys.iter().any(|(file, symbol)| match symbol {
+            Symbol::Local(_) => file.as_ref() == Some(uri),
+            _ => true,
+        });
+        if !wanted {
+            continue;
         }
-        if let Some(var) = &varsel.var.get() {
-            debug!("var {} {:?} {:?}", var.name, var.loc, var.ty.get());
-            state.definitions.push((
-                range_name(&loc, varsel.name),
-                range_name(&var.loc, var.name),
-            ));
+        for (range, occurrence) in file.occurrences.iter() {
+            if (declarations || !occurrence.declaration)
+                && keys.contains(&hierarchy.resolve(uri, &occurrence.symbol))
+            {
+                locations.push(Location {
+                    uri: uri.clone(),
+                    range: *range,
+                });
+            }
         }
     }
+    locations.sort_by(|a, b| (a.uri.as_str(), a.range.start).cmp(&(b.uri.as_str(), b.range.start)));
+    locations
+}
 
-    fn var<'a>(&self, var: &VarDef<'a>, state: &mut FileState) {
-        state.hovers.push((
-            range_name(&var.loc, var.name),
-            Hover {
-                contents: HoverContents::Scalar(MarkedString::from_markdown(format!(
-                    "{}: {:?}",
-                    var.name,
-                    var.ty.get(),
-                ))),
-                range: Some(range(&var.loc)),
-            },
-        ));
+/// Answers the requests that `LanguageServer` has no methods for
+#[derive(Debug)]
+struct Extensions {
+    state: Arc<State>,
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> std::result::Result<T, String> {
+    serde_json::from_value(params).map_err(|err| err.to_string())
+}
+
+impl Extension for Extensions {
+    fn capabilities(&self) -> Value {
+        json!({ "callHierarchyProvider": true, "typeHierarchyProvider": true })
     }
 
-    fn stmt<'a>(&self, stmt: &Stmt<'a>, state: &mut FileState) {
-        match &stmt.kind {
-            StmtKind::Assign(assign) => {
-                self.expr(&assign.dst, state);
-                self.expr(&assign.src, state);
+    fn handles(&self, method: &str) -> bool {
+        EXTENSIONS.contains(&method)
+    }
+
+    fn request(&self, method: &str, params: Value) -> Option<std::result::Result<Value, String>> {
+        debug!("{}", method);
+        let entries = self.state.entries();
+        let hierarchy = Hierarchy::new(&entries);
+        let result = match method {
+            "textDocument/prepareCallHierarchy" => {
+                parse(params).map(|params: TextDocumentPositionParams| {
+                    let items = self
+                        .state
+                        .file_at(&params.text_document.uri, params.position)
+                        .and_then(|file| {
+                            calls::prepare(
+                                &hierarchy,
+                                &params.text_document.uri,
+                                &file,
+                                params.position,
+                            )
+                        });
+                    json!(items)
+                })
             }
-            StmtKind::LocalVarDef(var) => {
-                self.var(var, state);
-                if let Some((_loc, expr)) = &var.init {
-                    self.expr(expr, state);
-                }
+            "callHierarchy/incomingCalls" => {
+                parse(params).map(|params: CallHierarchyCallsParams| {
+                    json!(calls::incoming(&hierarchy, &params.item))
+                })
             }
-            StmtKind::ExprEval(expr) => {
-                self.expr(expr, state);
+            "callHierarchy/outgoingCalls" => {
+                parse(params).map(|params: CallHierarchyCallsParams| {
+                    json!(calls::outgoing(&hierarchy, &params.item))
+                })
             }
-            StmtKind::If(i) => {
-                self.expr(&i.cond, state);
-                self.block(&i.on_true, state);
-                if let Some(f) = &i.on_false {
-                    self.block(f, state);
-                }
+            "textDocument/prepareTypeHierarchy" => {
+                parse(params).map(|params: TextDocumentPositionParams| {
+                    let items = self
+                        .state
+                        .file_at(&params.text_document.uri, params.position)
+                        .and_then(|file| {
+                            classes::prepare(
+                                &hierarchy,
+                                &params.text_document.uri,
+                                &file,
+                                params.position,
+                            )
+                        });
+                    json!(items)
+                })
             }
-            StmtKind::While(w) => {
-                self.expr(&w.cond, state);
-                self.block(&w.body, state);
+            "typeHierarchy/supertypes" => parse(params).map(|params: TypeHierarchyParams| {
+                json!(classes::supertypes(&hierarchy, &params.item))
+            }),
+            "typeHierarchy/subtypes" => parse(params).map(|params: TypeHierarchyParams| {
+                json!(classes::subtypes(&hierarchy, &params.item))
+            }),
+            "decaf/classHierarchy" => Ok(json!(classes::forest(&hierarchy))),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+impl Backend {
+    fn encoding(&self) -> PositionEncoding {
+        *self.encoding.read().unwrap()
+    }
+
+    /// Analyse `content` in the background once `delay` has passed without a newer version
+    fn update(
+        &self,
+        printer: &Printer,
+        uri: Url,
+        content: String,
+        version: Option<i64>,
+        delay: Duration,
+    ) {
+        let generation = self.scheduler.next(&uri);
+        let analyzer = Analyzer::new(uri.clone(), self.encoding(), self.config());
+        let scheduler = self.scheduler.clone();
+        let state = self.state.clone();
+        let printer = printer.clone();
+        tokio::spawn(async move {
+            if delay > Duration::from_millis(0) {
+                time::delay_for(delay).await;
             }
-            StmtKind::For(f) => {
-                self.stmt(&f.init, state);
-                self.expr(&f.cond, state);
-                self.stmt(&f.update, state);
-                self.block(&f.body, state);
+            if !scheduler.is_latest(&uri, generation) {
+                return;
             }
-            StmtKind::Return(Some(expr)) => {
-                self.expr(&expr, state);
+
+            let run = {
+                let scheduler = scheduler.clone();
+                let uri = uri.clone();
+                task::spawn_blocking(move || {
+                    let cancelled = || !scheduler.is_latest(&uri, generation);
+                    analyzer.analyze(&content, version, &cancelled)
+                })
+            };
+            let mut analysis = match run.await {
+                Ok(Some(analysis)) => analysis,
+                _ => return,
+            };
+            if let Some(previous) = state.get_file(&uri) {
+                analysis.inherit(&previous);
             }
-            StmtKind::Print(exprs) => {
-                for expr in exprs.iter() {
-                    self.expr(&expr, state);
+            let analysis = Arc::new(analysis);
+
+            // checked under the lock so that a newer run is never overwritten
+            {
+                let mut files = state.files.write().unwrap();
+                if !scheduler.is_latest(&uri, generation) {
+                    return;
                 }
+                files.insert(uri.clone(), analysis.clone());
             }
-            StmtKind::Block(block) => {
-                self.block(&block, state);
+            // published without the lock, so that requests do not wait on the client
+            let diagnostics = analysis.diagnostics.clone();
+            printer.publish_diagnostics(uri, diagnostics, version);
+        });
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// The config file lives in the first workspace root
+    fn config_path(&self) -> Option<PathBuf> {
+        let roots = self.roots.lock().unwrap();
+        roots.first().map(|root| root.join(config::FILE_NAME))
+    }
+
+    fn source_roots(&self) -> Vec<PathBuf> {
+        let roots = self.roots.lock().unwrap().clone();
+        match self.config_path() {
+            Some(path) => {
+                let dir = path.parent().unwrap_or(&path);
+                self.config().source_roots(dir, &roots)
             }
-            _ => {}
+            None => roots,
         }
     }
 
-    fn block<'a>(&self, block: &Block<'a>, state: &mut FileState) {
-        for stmt in block.stmt.iter() {
-            self.stmt(stmt, state);
-        }
+    fn in_workspace(&self, path: &Path) -> bool {
+        workspace::is_decaf(path)
+            && self
+                .source_roots()
+                .iter()
+                .any(|root| path.starts_with(root))
     }
 
-    fn field<'a>(
-        &self,
-        uri: Url,
-        class: &ClassDef<'a>,
-        field: &FieldDef<'a>,
-        state: &mut FileState,
-    ) {
-        match field {
-            syntax::FieldDef::FuncDef(func) => {
-                state.symbols.push(SymbolInformation {
-                    name: func.name.to_string(),
-                    kind: SymbolKind::Method,
-                    deprecated: None,
-                    location: Location {
-                        uri: uri.clone(),
-                        range: range(&func.loc),
-                    },
-                    container_name: Some(class.name.to_string()),
-                });
-                state.hovers.push((
-                    range_name(&func.loc, func.name),
-                    Hover {
-                        contents: HoverContents::Scalar(MarkedString::from_markdown(format!(
-                            "{}: {:?}",
-                            func.name,
-                            syntax::ty::Ty::mk_func(func)
-                        ))),
-                        range: Some(range(&func.loc)),
-                    },
-                ));
-                for param in func.param.iter() {
-                    self.var(param, state);
+    /// Read the config file, keeping the previous config if it is invalid.
+    /// Problems are published as diagnostics on the config file.
+    fn load_config(&self, printer: &Printer) {
+        let path = match self.config_path() {
+            Some(path) => path,
+            None => return,
+        };
+        let uri = match Url::from_file_path(&path) {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+        let mut diagnostics = Vec::new();
+        match fs::read_to_string(&path) {
+            Ok(text) => match Config::parse(&text) {
+                Ok(config) => *self.config.write().unwrap() = Arc::new(config),
+                Err(err) => {
+                    warn!("invalid {}: {}", path.display(), err.message);
+                    let index = LineIndex::new(&text, self.encoding());
+                    diagnostics.push(err.diagnostic(&index));
                 }
-                self.block(&func.body, state);
-            }
-            syntax::FieldDef::VarDef(var) => {
-                state.symbols.push(SymbolInformation {
-                    name: var.name.to_string(),
-                    kind: SymbolKind::Field,
-                    deprecated: None,
-                    location: Location {
-                        uri: uri.clone(),
-                        range: range(&var.loc),
-                    },
-                    container_name: Some(class.name.to_string()),
-                });
-                self.var(var, state);
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                *self.config.write().unwrap() = Arc::new(Config::default());
             }
+            Err(err) => warn!("cannot read {}: {}", path.display(), err),
         }
+        printer.publish_diagnostics(uri, diagnostics, None);
     }
 
-    fn class<'a>(&self, uri: Url, class: &ClassDef<'a>, state: &mut FileState) {
-        let class_range = range2(&class.loc, &class.end);
-        state.symbols.push(SymbolInformation {
-            name: class.name.to_string(),
-            kind: SymbolKind::Class,
-            deprecated: None,
-            location: Location {
-                uri: uri.clone(),
-                range: class_range,
-            },
-            container_name: None,
-        });
-        state.hovers.push((
-            range_name(&class.loc, class.name),
-            Hover {
-                contents: HoverContents::Scalar(MarkedString::from_markdown(
-                    class.name.to_string(),
-                )),
-                range: Some(class_range),
-            },
-        ));
-        state.ranges.push(FoldingRange {
-            start_line: (class.loc.0 - 1) as u64,
-            start_character: None,
-            end_line: (class.end.0 - 1) as u64,
-            end_character: None,
-            kind: Some(FoldingRangeKind::Region),
+    /// Read the source roots on a blocking task, since walking and reading a large tree
+    /// would hold up the requests that follow. Then analyse every file found and drop
+    /// the indexed files that are no longer among them.
+    fn index_workspace(&self, printer: &Printer) {
+        let roots = self.source_roots();
+        let backend = self.clone();
+        let printer = printer.clone();
+        tokio::spawn(async move {
+            let sources = match task::spawn_blocking(move || workspace::read(&roots)).await {
+                Ok(sources) => sources,
+                Err(err) => {
+                    warn!("cannot index the workspace: {}", err);
+                    return;
+                }
+            };
+            let paths: HashSet<&PathBuf> = sources.iter().map(|(path, _)| path).collect();
+            let indexed: Vec<Url> = backend
+                .state
+                .files
+                .read()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect();
+            for uri in indexed {
+                match uri.to_file_path() {
+                    Ok(path) if paths.contains(&path) => {}
+                    _ => backend.forget_file(&printer, uri),
+                }
+            }
+            for (path, content) in sources {
+                debug!("indexing {}", path.display());
+                backend.index_text(&printer, &path, content);
+            }
         });
+    }
 
-        for field in class.field.iter() {
-            self.field(uri.clone(), class, field, state);
+    /// Analyse every file again after the config changed, following changes to the source roots
+    fn reindex(&self, printer: &Printer) {
+        self.index_workspace(printer);
+        let open: Vec<(Url, String, Option<i64>)> = self
+            .documents
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(uri, document)| (uri.clone(), document.text(), document.version()))
+            .collect();
+        for (uri, content, version) in open {
+            self.update(printer, uri, content, version, Duration::from_millis(0));
         }
     }
 
-    fn program<'a>(&self, uri: Url, program: &Program<'a>, state: &mut FileState) {
-        for class in program.class.iter() {
-            self.class(uri.clone(), class, state);
+    fn is_config(&self, uri: &Url) -> bool {
+        match (uri.to_file_path(), self.config_path()) {
+            (Ok(path), Some(config)) => path == config,
+            _ => false,
         }
     }
 
-    fn update(&self, printer: &Printer, uri: Url, content: &str) {
-        // hovers
-        let mut tokens = syntax::parser::Lexer::new(content.as_bytes());
-        let mut hovers = Vec::new();
-        loop {
-            use syntax::parser::TokenKind::*;
-            let tok = tokens.next();
-            if tok.ty == _Eof {
-                break;
-            }
-
-            if tok.ty == Id
-                || tok.ty == Le
-                || tok.ty == Ge
-                || tok.ty == Eq
-                || tok.ty == Ne
-                || tok.ty == And
-                || tok.ty == Add
-                || tok.ty == Sub
-                || tok.ty == Mul
-                || tok.ty == Div
-                || tok.ty == Mod
-                || tok.ty == Assign
-                || tok.ty == Lt
-                || tok.ty == Gt
-                || tok.ty == Dot
-                || tok.ty == Comma
-                || tok.ty == Semi
-                || tok.ty == Not
-                || tok.ty == LPar
-                || tok.ty == RPar
-                || tok.ty == LBrk
-                || tok.ty == RBrk
-                || tok.ty == LBrc
-                || tok.ty == RBrc
-                || tok.ty == Colon
-            {
-                continue;
-            }
+    /// Drop a file that no longer exists, unless the client has it open
+    fn forget_file(&self, printer: &Printer, uri: Url) {
+        if self.documents.lock().unwrap().get(&uri).is_some() {
+            return;
+        }
+        self.scheduler.next(&uri);
+        self.state.files.write().unwrap().remove(&uri);
+        printer.publish_diagnostics(uri, vec![], None);
+    }
 
-            let range = token(&tok);
-            hovers.push((
-                range,
-                Hover {
-                    contents: HoverContents::Scalar(MarkedString::from_markdown(match tok.ty {
-                        IntLit => format!("Integer Literal"),
-                        StringLit => format!("String Literal"),
-                        UntermString => format!("Unterminated String Literal"),
-                        _ => format!("{:?}", tok.ty),
-                    })),
-                    range: None,
-                },
-            ));
+    /// Index a file from disk, unless the client has it open
+    fn index_file(&self, printer: &Printer, path: &Path) {
+        match fs::read_to_string(path) {
+            Ok(content) => self.index_text(printer, path, content),
+            Err(err) => warn!("cannot read {}: {}", path.display(), err),
         }
-        let mut state = self.state.lock().unwrap();
-        state.get_file(&uri).hovers = hovers;
-        state.get_file(&uri).content = String::from(content);
-        drop(state);
-
-        // symbols
-        match syntax::parser::work(content, &syntax::ASTAlloc::default()) {
-            Ok(program) => {
-                let mut diag = vec![];
-
-                let alloc = typeck::TypeCkAlloc::default();
-                match typeck::work(program, &alloc) {
-                    Ok(_) => {
-                        // Passes type checking
-                    }
-                    Err(errors) => {
-                        for err in errors.0.iter() {
-                            diag.push(Diagnostic {
-                                range: range(&err.0),
-                                severity: None,
-                                code: None,
-                                source: None,
-                                message: format!("{:?}", err.1),
-                                related_information: None,
-                                tags: None,
-                            });
-                        }
-                    }
-                }
+    }
 
-                printer.publish_diagnostics(uri.clone(), diag, None);
-
-                // symbols, hovers and ranges
-                let mut file_state = FileState::default();
-                self.program(uri.clone(), program, &mut file_state);
-                file_state.symbols.reverse();
-                debug!("hovers {:?}", file_state.hovers);
-                debug!("def {:?}", file_state.definitions);
-                let mut state = self.state.lock().unwrap();
-                state.get_file(&uri).symbols = file_state.symbols;
-                state.get_file(&uri).hovers.append(&mut file_state.hovers);
-                state.get_file(&uri).ranges = file_state.ranges;
-                state.get_file(&uri).definitions = file_state.definitions;
-                drop(state);
-            }
-            Err(errors) => {
-                let mut diag = Vec::new();
-                for err in errors.0.iter() {
-                    diag.push(Diagnostic {
-                        range: range(&err.0),
-                        severity: None,
-                        code: None,
-                        source: None,
-                        message: format!("{:?}", err.1),
-                        related_information: None,
-                        tags: None,
-                    });
-                }
-                printer.publish_diagnostics(uri, diag, None);
-            }
+    /// Analyse the contents of a file on disk, unless the client has it open
+    fn index_text(&self, printer: &Printer, path: &Path, content: String) {
+        let uri = match Url::from_file_path(path) {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+        if self.documents.lock().unwrap().get(&uri).is_some() {
+            return;
         }
+        self.update(printer, uri, content, None, Duration::from_millis(0));
     }
 
     fn complete(&self, _loc: Loc, name: &str) -> Vec<CompletionItem> {
@@ -393,18 +502,46 @@ impl Backend {
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    fn initialize(&self, _: &Printer, _: InitializeParams) -> Result<InitializeResult> {
+    fn initialize(&self, _: &Printer, params: InitializeParams) -> Result<InitializeResult> {
+        *self.roots.lock().unwrap() = workspace::roots(&params);
+        let watch_files = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|capability| capability.dynamic_registration)
+            .unwrap_or(false);
+        self.watch_files.store(watch_files, Ordering::SeqCst);
+        let hierarchical_symbols = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.document_symbol.as_ref())
+            .and_then(|capability| capability.hierarchical_document_symbol_support)
+            .unwrap_or(false);
+        self.hierarchical_symbols
+            .store(hierarchical_symbols, Ordering::SeqCst);
         Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::Full,
+                    TextDocumentSyncKind::Incremental,
                 )),
                 workspace_symbol_provider: Some(true),
                 document_symbol_provider: Some(true),
                 hover_provider: Some(true),
                 folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
                 definition_provider: Some(true),
+                type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
+                implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
+                references_provider: Some(true),
+                document_highlight_provider: Some(true),
+                rename_provider: Some(RenameProviderCapability::Options(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: None,
+                    },
+                })),
                 completion_provider: Some(CompletionOptions {
                     resolve_provider: None,
                     trigger_characters: Some(vec![String::from("R"), String::from("P")]),
@@ -417,6 +554,31 @@ impl LanguageServer for Backend {
         })
     }
 
+    fn initialized(&self, printer: &Printer, _: InitializedParams) {
+        debug!("initialized");
+        if self.watch_files.load(Ordering::SeqCst) {
+            let options = DidChangeWatchedFilesRegistrationOptions {
+                watchers: vec![
+                    FileSystemWatcher {
+                        glob_pattern: String::from("**/*.decaf"),
+                        kind: None,
+                    },
+                    FileSystemWatcher {
+                        glob_pattern: format!("**/{}", config::FILE_NAME),
+                        kind: None,
+                    },
+                ],
+            };
+            printer.register_capability(vec![Registration {
+                id: String::from("decaf-watched-files"),
+                method: String::from("workspace/didChangeWatchedFiles"),
+                register_options: Some(serde_json::to_value(options).unwrap()),
+            }]);
+        }
+        self.load_config(printer);
+        self.index_workspace(printer);
+    }
+
     async fn shutdown(&self) -> Result<()> {
         debug!("shutdown");
         Ok(())
@@ -424,10 +586,9 @@ impl LanguageServer for Backend {
 
     async fn symbol(&self, _: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
         debug!("symbol");
-        let state = self.state.lock().unwrap();
         let mut symbols = Vec::new();
-        for (_, file) in state.files.iter() {
-            symbols.append(&mut file.symbols.clone());
+        for file in self.state.all_files() {
+            symbols.extend(file.symbols.iter().cloned());
         }
         Ok(Some(symbols))
     }
@@ -435,9 +596,8 @@ impl LanguageServer for Backend {
     /*
     async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<FoldingRange>> {
         debug!("folding");
-        let mut state = self.state.lock().unwrap();
-        let file = state.get_file(&params.text_document.uri);
-        Ok(Some(file.ranges.clone()))
+        let file = self.state.get_file(&params.text_document.uri);
+        Ok(file.map(|file| file.ranges.clone()))
     }
     */
 
@@ -449,47 +609,175 @@ impl LanguageServer for Backend {
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
         debug!("complete");
         let position = params.text_document_position.position;
-        let mut state = self.state.lock().unwrap();
-        let file = state.get_file(&params.text_document_position.text_document.uri);
-        let lines: Vec<&str> = file.content.split("\n").collect();
-        if let Some(line) = lines.get(position.line as usize) {
-            let part = &line[..position.character as usize];
-            if let Some(name) = part.rmatches(char::is_alphabetic).next() {
-                debug!("{}", name);
-                let loc = Loc(position.line as u32 + 1, position.character as u32 + 1);
-                return Ok(Some(CompletionResponse::Array(self.complete(loc, name))));
-            }
+        // the analysis lags behind the edits, so read what was just typed from the document
+        let text = match self
+            .documents
+            .lock()
+            .unwrap()
+            .get(&params.text_document_position.text_document.uri)
+        {
+            Some(document) => document.text(),
+            None => return Ok(None),
+        };
+        let index = LineIndex::new(&text, self.encoding());
+        let line = index.line(position.line as usize);
+        let part = &line[..index.col(position)];
+        if let Some(name) = part.rmatches(char::is_alphabetic).next() {
+            debug!("{}", name);
+            let loc = index.to_loc(position);
+            return Ok(Some(CompletionResponse::Array(self.complete(loc, name))));
         }
         Ok(None)
     }
 
     async fn hover(&self, params: TextDocumentPositionParams) -> Result<Option<Hover>> {
         debug!("hover");
-        let mut state = self.state.lock().unwrap();
-        let file = state.get_file(&params.text_document.uri);
-        let mut result: Option<(Range, Hover)> = None;
-        for (range, hover) in file.hovers.iter() {
-            if range.start <= params.position && range.end >= params.position {
-                result = Some(if let Some((old_range, old_hover)) = result {
-                    if range.end <= old_range.end && range.start >= old_range.start {
-                        (*range, hover.clone())
-                    } else {
-                        (old_range, old_hover)
-                    }
-                } else {
-                    (*range, hover.clone())
-                });
+        let file = match self.state.get_file(&params.text_document.uri) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        // in edited code only the lexer tokens are up to date
+        let semantic = if file.known(params.position) {
+            file.hovers.innermost(params.position)
+        } else {
+            None
+        };
+        let hover = semantic.or_else(|| file.tokens.innermost(params.position));
+        Ok(hover.map(|(_, hover)| hover.clone()))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        debug!("references");
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let file = match self.state.file_at(&uri, position) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let symbol = match file.occurrences.innermost(position) {
+            Some((_, occurrence)) => occurrence.symbol.clone(),
+            None => return Ok(None),
+        };
+        let include_declaration = params.context.include_declaration;
+        Ok(Some(self.state.occurrences(
+            &uri,
+            &symbol,
+            include_declaration,
+        )))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        debug!("prepareRename");
+        let file = match self
+            .state
+            .file_at(&params.text_document.uri, params.position)
+        {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let (range, occurrence) = match file.occurrences.innermost(params.position) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let entries = self.state.entries();
+        let hierarchy = Hierarchy::new(&entries);
+        let key = hierarchy.resolve(&params.text_document.uri, &occurrence.symbol);
+        if rename::renamable(&hierarchy, &params.text_document.uri, &key).is_some() {
+            return Ok(None);
+        }
+        let text = file.index.text();
+        let name = &text[file.index.offset(range.start)..file.index.offset(range.end)];
+        Ok(Some(PrepareRenameResponse::RangeWithPlaceholder {
+            range: *range,
+            placeholder: String::from(name),
+        }))
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        debug!("rename");
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let file = match self.state.file_at(&uri, position) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let symbol = match file.occurrences.innermost(position) {
+            Some((_, occurrence)) => occurrence.symbol.clone(),
+            None => return Ok(None),
+        };
+        let entries = self.state.entries();
+        let hierarchy = Hierarchy::new(&entries);
+        let key = hierarchy.resolve(&uri, &symbol);
+        if let Some(reason) = rename::conflict(&hierarchy, &uri, &file, &key, &params.new_name) {
+            return Err(Error::invalid_params(reason));
+        }
+
+        let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+        let keys = rename::related(&hierarchy, &key);
+        for location in locations(&entries, &hierarchy, &keys, true) {
+            changes.entry(location.uri).or_default().push(TextEdit {
+                range: location.range,
+                new_text: params.new_name.clone(),
+            });
+        }
+        for (uri, edits) in changes.iter_mut() {
+            // ranges of a file carried over from a version that parsed may be off
+            if entries
+                .iter()
+                .any(|(other, file)| other == uri && file.stale)
+            {
+                return Err(Error::invalid_params(format!(
+                    "cannot rename while {} has syntax errors",
+                    uri
+                )));
             }
+            edits.sort_by_key(|edit| edit.range.start);
+            edits.dedup_by_key(|edit| edit.range);
         }
-        Ok(result.map(|res| res.1))
+        Ok(Some(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+        }))
     }
 
     async fn document_highlight(
         &self,
-        _: TextDocumentPositionParams,
+        params: TextDocumentPositionParams,
     ) -> Result<Option<Vec<DocumentHighlight>>> {
         debug!("highlight");
-        Ok(None)
+        let file = match self
+            .state
+            .file_at(&params.text_document.uri, params.position)
+        {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let entries = self.state.entries();
+        let hierarchy = Hierarchy::new(&entries);
+        let uri = &params.text_document.uri;
+        let key = match file.occurrences.innermost(params.position) {
+            Some((_, occurrence)) => hierarchy.resolve(uri, &occurrence.symbol),
+            None => return Ok(None),
+        };
+        let highlights = file
+            .occurrences
+            .iter()
+            .filter(|(_, occurrence)| hierarchy.resolve(uri, &occurrence.symbol) == key)
+            .map(|(range, occurrence)| DocumentHighlight {
+                range: *range,
+                kind: Some(if occurrence.write {
+                    DocumentHighlightKind::Write
+                } else if occurrence.declaration {
+                    DocumentHighlightKind::Text
+                } else {
+                    DocumentHighlightKind::Read
+                }),
+            })
+            .collect();
+        Ok(Some(highlights))
     }
 
     async fn document_symbol(
@@ -497,9 +785,15 @@ impl LanguageServer for Backend {
         params: DocumentSymbolParams,
     ) -> Result<Option<DocumentSymbolResponse>> {
         debug!("documentSymbol");
-        let mut state = self.state.lock().unwrap();
-        let file = state.get_file(&params.text_document.uri);
-        Ok(Some(DocumentSymbolResponse::Flat(file.symbols.clone())))
+        let file = match self.state.get_file(&params.text_document.uri) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        if self.hierarchical_symbols.load(Ordering::SeqCst) {
+            Ok(Some(DocumentSymbolResponse::Nested(file.outline())))
+        } else {
+            Ok(Some(DocumentSymbolResponse::Flat(file.symbols.clone())))
+        }
     }
 
     async fn goto_definition(
@@ -507,28 +801,71 @@ impl LanguageServer for Backend {
         params: TextDocumentPositionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
         debug!("definition");
-        let mut state = self.state.lock().unwrap();
-        let file = state.get_file(&params.text_document.uri);
-        let mut result: Option<(Range, Range)> = None;
-        for (range, def) in file.definitions.iter() {
-            if range.start <= params.position && range.end >= params.position {
-                result = Some(if let Some((old_range, old_hover)) = result {
-                    if range.end <= old_range.end && range.start >= old_range.start {
-                        (*range, def.clone())
-                    } else {
-                        (old_range, old_hover)
-                    }
-                } else {
-                    (*range, def.clone())
-                });
-            }
-        }
-        Ok(result.map(|res| {
-            GotoDefinitionResponse::Scalar(Location {
+        let file = match self
+            .state
+            .file_at(&params.text_document.uri, params.position)
+        {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        if let Some((_, def)) = file.definitions.innermost(params.position) {
+            return Ok(Some(GotoDefinitionResponse::Scalar(Location {
                 uri: params.text_document.uri.clone(),
-                range: res.1,
-            })
-        }))
+                range: *def,
+            })));
+        }
+        let location = file
+            .occurrences
+            .innermost(params.position)
+            .and_then(|(_, occurrence)| {
+                self.state
+                    .declaration(&params.text_document.uri, &occurrence.symbol)
+            });
+        Ok(location.map(GotoDefinitionResponse::Scalar))
+    }
+
+    async fn goto_type_definition(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        debug!("typeDefinition");
+        let file = match self
+            .state
+            .file_at(&params.text_document.uri, params.position)
+        {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let location = file
+            .types
+            .innermost(params.position)
+            .and_then(|(_, class)| {
+                let symbol = Symbol::Class(class.clone());
+                self.state.declaration(&params.text_document.uri, &symbol)
+            });
+        Ok(location.map(GotoDefinitionResponse::Scalar))
+    }
+
+    async fn goto_implementation(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        debug!("implementation");
+        let file = match self
+            .state
+            .file_at(&params.text_document.uri, params.position)
+        {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let symbol = match file.occurrences.innermost(params.position) {
+            Some((_, occurrence)) => &occurrence.symbol,
+            None => return Ok(None),
+        };
+        Ok(Some(GotoDefinitionResponse::Array(
+            self.state
+                .implementations(&params.text_document.uri, symbol),
+        )))
     }
 
     async fn goto_declaration(
@@ -541,26 +878,75 @@ impl LanguageServer for Backend {
     fn did_open(&self, printer: &Printer, params: DidOpenTextDocumentParams) {
         debug!("didOpen");
         let uri = params.text_document.uri;
-        if let Ok(path) = uri.to_file_path() {
-            if let Ok(content) = fs::read_to_string(path) {
-                self.update(printer, uri, &content);
-            }
-        }
+        let version = Some(params.text_document.version);
+        let content = params.text_document.text;
+        self.documents
+            .lock()
+            .unwrap()
+            .open(uri.clone(), &content, version);
+        self.update(printer, uri, content, version, Duration::from_millis(0));
     }
 
     fn did_change(&self, printer: &Printer, params: DidChangeTextDocumentParams) {
         debug!("didChange");
         let uri = params.text_document.uri;
-        self.update(printer, uri, &params.content_changes[0].text);
+        let mut documents = self.documents.lock().unwrap();
+        let document = match documents.change(
+            &uri,
+            &params.content_changes,
+            params.text_document.version,
+            self.encoding(),
+        ) {
+            Some(document) => document,
+            None => {
+                warn!("didChange for unopened document {}", uri);
+                return;
+            }
+        };
+        let content = document.text();
+        let version = document.version();
+        drop(documents);
+        self.update(printer, uri, content, version, DEBOUNCE);
+    }
+
+    fn did_change_watched_files(&self, printer: &Printer, params: DidChangeWatchedFilesParams) {
+        debug!("didChangeWatchedFiles");
+        if params
+            .changes
+            .iter()
+            .any(|change| self.is_config(&change.uri))
+        {
+            self.load_config(printer);
+            self.reindex(printer);
+        }
+        for change in params.changes {
+            if self.is_config(&change.uri) {
+                continue;
+            }
+            match change.typ {
+                FileChangeType::Created | FileChangeType::Changed => {
+                    if let Ok(path) = change.uri.to_file_path() {
+                        if self.in_workspace(&path) {
+                            self.index_file(printer, &path);
+                        }
+                    }
+                }
+                FileChangeType::Deleted => {
+                    self.forget_file(printer, change.uri);
+                }
+            }
+        }
     }
 
     fn did_close(&self, printer: &Printer, params: DidCloseTextDocumentParams) {
         debug!("didClose");
-        let mut state = self.state.lock().unwrap();
-        if let Some(state) = state.files.get_mut(&params.text_document.uri) {
-            state.symbols.clear();
+        let uri = params.text_document.uri;
+        self.documents.lock().unwrap().close(&uri);
+        // files in the workspace stay indexed with their on-disk content
+        match uri.to_file_path() {
+            Ok(path) if self.in_workspace(&path) => self.index_file(printer, &path),
+            _ => self.forget_file(printer, uri),
         }
-        printer.publish_diagnostics(params.text_document.uri, vec![], None);
     }
 }
 
@@ -571,9 +957,17 @@ async fn main() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, messages) = LspService::new(Backend::default());
+    let encoding = Arc::new(RwLock::new(PositionEncoding::default()));
+    let state = Arc::new(State::default());
+    let backend = Backend {
+        state: state.clone(),
+        encoding: encoding.clone(),
+        ..Backend::default()
+    };
+    let extensions = Arc::new(Extensions { state });
+    let (service, messages) = LspService::new(backend);
     Server::new(stdin, stdout)
         .interleave(messages)
-        .serve(service)
+        .serve(Router::new(service, encoding, extensions))
         .await;
 }
@@ -0,0 +1,180 @@
+use crate::line_index::LineIndex;
+use crate::tokens::{self, Tok};
+use common::Loc;
+use syntax::parser::TokenKind;
+
+/// Index of the `}` closing the `{` at `open`, or of the last token before `_Eof`
+fn matching(tokens: &[Tok], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, tok) in tokens.iter().enumerate().skip(open) {
+        match tok.ty {
+            TokenKind::LBrc => depth += 1,
+            TokenKind::RBrc => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens.len().saturating_sub(2)
+}
+
+/// Token range `[start, end)` to drop for an error at token `i`: the statement or
+/// member around it, synchronizing on `;` and braces, or the whole class if the
+/// error is in a class header
+fn region(tokens: &[Tok], i: usize) -> (usize, usize) {
+    use TokenKind::*;
+    let mut start = i;
+    while start > 0 && !matches!(tokens[start - 1].ty, Semi | LBrc | RBrc) {
+        start -= 1;
+    }
+    if tokens[start].ty == Class {
+        let open = (start..tokens.len())
+            .find(|&j| tokens[j].ty == LBrc)
+            .unwrap_or(i);
+        return (start, matching(tokens, open) + 1);
+    }
+    let mut end = i;
+    while !matches!(tokens[end].ty, Semi | LBrc | RBrc | _Eof) {
+        end += 1;
+    }
+    match tokens[end].ty {
+        Semi => end += 1,
+        LBrc => end = matching(tokens, end) + 1,
+        _ => {}
+    }
+    if start == end && tokens[i].ty != _Eof {
+        // nothing between two sync points, so the sync token itself is unexpected
+        end = i + 1;
+    }
+    (start, end)
+}
+
+/// Blank out the code around each error location so that the rest of the file can
+/// be parsed. Line breaks and byte lengths are kept, so locations outside the
+/// blanked code do not move.
+/// Returns the repaired text with the byte ranges blanked, or `None` if nothing could be removed.
+pub fn blank(
+    text: &str,
+    index: &LineIndex,
+    errors: &[Loc],
+) -> Option<(String, Vec<(usize, usize)>)> {
+    let tokens = tokens::lex(text, index);
+    let mut ranges = Vec::new();
+    let mut unclosed = false;
+    for loc in errors.iter() {
+        let at = index.loc_offset(loc);
+        let i = tokens::at(&tokens, at);
+        if tokens[i].ty == TokenKind::_Eof && at >= text.len() {
+            unclosed = true;
+            continue;
+        }
+        // lexer errors may sit between tokens, so always cover the error location itself
+        let at_end = at
+            + text
+                .get(at..)
+                .and_then(|rest| rest.chars().next())
+                .map_or(0, char::len_utf8);
+        let (start, end) = region(&tokens, i);
+        if start == end {
+            ranges.push((at, at_end));
+        } else {
+            ranges.push((tokens[start].start.min(at), tokens[end - 1].end.max(at_end)));
+        }
+    }
+
+    let mut progress = false;
+    let mut repaired = String::with_capacity(text.len());
+    for (i, ch) in text.char_indices() {
+        if !ch.is_whitespace() && ranges.iter().any(|&(from, to)| from <= i && i < to) {
+            // one space per byte, so that byte columns after it stay the same
+            for _ in 0..ch.len_utf8() {
+                repaired.push(' ');
+            }
+            progress = true;
+        } else {
+            repaired.push(ch);
+        }
+    }
+    if unclosed {
+        // the file ended early, close every open brace
+        let depth = tokens.iter().fold(0i32, |depth, tok| match tok.ty {
+            TokenKind::LBrc => depth + 1,
+            TokenKind::RBrc => depth - 1,
+            _ => depth,
+        });
+        for _ in 0..depth {
+            repaired.push('}');
+            progress = true;
+        }
+    }
+    if progress {
+        Some((repaired, ranges))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_index::PositionEncoding;
+
+    /// Lexer location of the first occurrence of `needle`
+    fn loc(text: &str, needle: &str) -> Loc {
+        let offset = text.find(needle).unwrap();
+        let line = text[..offset].matches('\n').count();
+        let col = offset - text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        Loc(line as u32 + 1, col as u32 + 1)
+    }
+
+    fn repair(text: &str, errors: &[&str]) -> Option<(String, Vec<(usize, usize)>)> {
+        let index = LineIndex::new(text, PositionEncoding::Utf16);
+        let errors: Vec<Loc> = errors.iter().map(|needle| loc(text, needle)).collect();
+        blank(text, &index, &errors)
+    }
+
+    #[test]
+    fn statement() {
+        let text = "class Main {\n    static void main() {\n        int x = ;\n        Print(1);\n    }\n}\n";
+        let (repaired, ranges) = repair(text, &[";"]).unwrap();
+        let start = text.find("int x").unwrap();
+        assert_eq!(ranges, vec![(start, start + "int x = ;".len())]);
+        assert_eq!(repaired, text.replace("int x = ;", "         "));
+    }
+
+    #[test]
+    fn multi_byte() {
+        // one space per byte of 'é', so the lines keep their byte lengths
+        let text = "class Main {\n    static void main() {\n        Print(\"é\") x;\n    }\n}\n";
+        let (repaired, _) = repair(text, &["x;"]).unwrap();
+        assert_eq!(repaired.len(), text.len());
+        assert_eq!(repaired, text.replace("Print(\"é\") x;", &" ".repeat(14)));
+    }
+
+    #[test]
+    fn class_header() {
+        let text = "class A extends {\n    int x;\n}\nclass Main {}\n";
+        let (repaired, _) = repair(text, &["{"]).unwrap();
+        assert_eq!(repaired.lines().count(), text.lines().count());
+        assert_eq!(repaired.trim(), "class Main {}");
+    }
+
+    #[test]
+    fn unclosed() {
+        let text = "class Main {\n    static void main() {\n";
+        let index = LineIndex::new(text, PositionEncoding::Utf16);
+        let (repaired, ranges) = blank(text, &index, &[Loc(3, 1)]).unwrap();
+        assert!(ranges.is_empty());
+        assert_eq!(repaired, format!("{}}}}}", text));
+    }
+
+    #[test]
+    fn nothing() {
+        let text = "class Main {}\n";
+        let index = LineIndex::new(text, PositionEncoding::Utf16);
+        assert!(blank(text, &index, &[]).is_none());
+    }
+}
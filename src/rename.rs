@@ -0,0 +1,320 @@
+use crate::analysis::{Analysis, Symbol};
+use crate::hierarchy::{Hierarchy, Key};
+use syntax::parser::{Lexer, TokenKind};
+use tower_lsp::lsp_types::Url;
+
+/// Whether the lexer reads `name` as a single identifier, so not as a keyword
+pub fn is_identifier(name: &str) -> bool {
+    let mut lexer = Lexer::new(name.as_bytes());
+    let tok = lexer.next();
+    tok.ty == TokenKind::Id && tok.piece.len() == name.len() && lexer.next().ty == TokenKind::_Eof
+}
+
+/// Symbols renamed together with the symbol of `key`: a method is renamed with the
+/// methods it overrides and those overriding it
+pub fn related(hierarchy: &Hierarchy, key: &Key) -> Vec<Key> {
+    let name = match &key.1 {
+        Symbol::Method { name, .. } => name,
+        _ => return vec![key.clone()],
+    };
+    let class = match hierarchy.class(key) {
+        Some(class) => class,
+        None => return vec![key.clone()],
+    };
+    let family: Vec<Key> = hierarchy
+        .overrides(class, name)
+        .into_iter()
+        .map(|(uri, class)| {
+            let symbol = Symbol::Method {
+                class: class.name.clone(),
+                name: name.clone(),
+            };
+            (Some(uri.clone()), symbol)
+        })
+        .collect();
+    if family.contains(key) {
+        family
+    } else {
+        vec![key.clone()]
+    }
+}
+
+/// Why the symbol of `key`, which occurs in the file `uri`, cannot be renamed at all
+pub fn renamable(hierarchy: &Hierarchy, uri: &Url, key: &Key) -> Option<String> {
+    let (class, member) = match &key.1 {
+        Symbol::Local(_) => return None,
+        Symbol::Class(name) => (name, None),
+        Symbol::Field { class, name } | Symbol::Method { class, name } => (class, Some(name)),
+    };
+    match (class.as_str(), member.map(String::as_str), &key.1) {
+        ("Main", None, _) => {
+            return Some(String::from(
+                "class 'Main' is the entry point of the program",
+            ));
+        }
+        ("Main", Some("main"), Symbol::Method { .. }) => {
+            return Some(String::from(
+                "method 'main' is the entry point of the program",
+            ));
+        }
+        _ => {}
+    }
+    let declared = hierarchy.class(key).map_or(false, |(_, info)| {
+        member.map_or(true, |name| info.member(name).is_some())
+    });
+    if !declared {
+        return Some(format!(
+            "'{}' is not declared in the workspace",
+            member.unwrap_or(class)
+        ));
+    }
+    // each file is a program of its own; a class of another file is only found by its name
+    let ambiguous = match member {
+        None => hierarchy.declarations(class).len() > 1,
+        Some(_) => key.0.as_ref() != Some(uri) && hierarchy.declarations(class).len() > 1,
+    };
+    if ambiguous {
+        return Some(format!(
+            "class '{}' is declared in more than one file",
+            class
+        ));
+    }
+    None
+}
+
+/// Why renaming the symbol of `key`, which occurs in `file` at `uri`, to `new_name`
+/// would break the program
+pub fn conflict(
+    hierarchy: &Hierarchy,
+    uri: &Url,
+    file: &Analysis,
+    key: &Key,
+    new_name: &str,
+) -> Option<String> {
+    if !is_identifier(new_name) {
+        return Some(format!("'{}' is not a valid name", new_name));
+    }
+    if let Some(reason) = renamable(hierarchy, uri, key) {
+        return Some(reason);
+    }
+    match &key.1 {
+        Symbol::Class(_) => {
+            let (file, _) = hierarchy.class(key)?;
+            if hierarchy.get(file, new_name).is_some() {
+                return Some(format!("class '{}' already exists", new_name));
+            }
+        }
+        Symbol::Field { .. } | Symbol::Method { .. } => {
+            let class = hierarchy.class(key)?;
+            // a member with the new name in a superclass or subclass would be overridden or hidden
+            let classes = match &key.1 {
+                Symbol::Method { name, .. } => hierarchy.overrides(class, name),
+                _ => vec![class],
+            };
+            for class in classes {
+                for current in Some(class).into_iter().chain(hierarchy.descendants(class)) {
+                    if let Some(((_, owner), _)) = hierarchy.lookup(current, new_name) {
+                        return Some(format!(
+                            "'{}' is already declared in class '{}'",
+                            new_name, owner.name
+                        ));
+                    }
+                    // a local with the new name would capture the member's uses in its method
+                    let (_, current) = current;
+                    for member in current.members.iter() {
+                        if member.locals.iter().any(|local| local.name == new_name) {
+                            return Some(format!(
+                                "'{}' is already declared in method '{}' of class '{}'",
+                                new_name, member.name, current.name
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Symbol::Local(declaration) => {
+            let (class, member) = file.classes.iter().find_map(|class| {
+                let member = class.members.iter().find(|member| {
+                    member
+                        .locals
+                        .iter()
+                        .any(|local| local.selection == *declaration)
+                })?;
+                Some((class, member))
+            })?;
+            if member.locals.iter().any(|local| local.name == new_name) {
+                return Some(format!(
+                    "'{}' is already declared in method '{}'",
+                    new_name, member.name
+                ));
+            }
+            // the local would hide the member
+            let owner = hierarchy
+                .get(uri, &class.name)
+                .and_then(|class| hierarchy.lookup(class, new_name))
+                .map(|((_, owner), _)| owner)
+                .or_else(|| class.member(new_name).map(|_| class));
+            if let Some(owner) = owner {
+                return Some(format!(
+                    "'{}' is already declared in class '{}'",
+                    new_name, owner.name
+                ));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::tests::{analyze, find};
+    use std::sync::Arc;
+
+    const PROGRAM: &str = "class Main {
+    static void main() {
+        int count = 1;
+        int total = count;
+        Print(total);
+    }
+}
+
+class A {
+    int size;
+
+    int get() {
+        return size;
+    }
+}
+
+class B extends A {
+    int get() {
+        return 2;
+    }
+
+    int other() {
+        return 3;
+    }
+}
+";
+
+    fn files(sources: &[(&str, &str)]) -> Vec<(Url, Arc<Analysis>)> {
+        sources
+            .iter()
+            .map(|(uri, text)| (Url::parse(uri).unwrap(), Arc::new(analyze(uri, text))))
+            .collect()
+    }
+
+    fn member(uri: &Url, class: &str, name: &str, method: bool) -> Key {
+        let (class, name) = (String::from(class), String::from(name));
+        let symbol = if method {
+            Symbol::Method { class, name }
+        } else {
+            Symbol::Field { class, name }
+        };
+        (Some(uri.clone()), symbol)
+    }
+
+    fn class(uri: &Url, name: &str) -> Key {
+        (Some(uri.clone()), Symbol::Class(String::from(name)))
+    }
+
+    #[test]
+    fn identifiers() {
+        assert!(is_identifier("count"));
+        assert!(is_identifier("x1"));
+        assert!(!is_identifier("while"));
+        assert!(!is_identifier("Print"));
+        assert!(!is_identifier("1x"));
+        assert!(!is_identifier("a b"));
+        assert!(!is_identifier("a-b"));
+        assert!(!is_identifier(""));
+    }
+
+    #[test]
+    fn overrides_renamed_together() {
+        let entries = files(&[("file:///main.decaf", PROGRAM)]);
+        let hierarchy = Hierarchy::new(&entries);
+        let uri = &entries[0].0;
+        assert_eq!(
+            related(&hierarchy, &member(uri, "B", "get", true)),
+            vec![member(uri, "A", "get", true), member(uri, "B", "get", true)]
+        );
+        assert_eq!(
+            related(&hierarchy, &member(uri, "B", "other", true)),
+            vec![member(uri, "B", "other", true)]
+        );
+    }
+
+    #[test]
+    fn conflicts() {
+        let entries = files(&[("file:///main.decaf", PROGRAM)]);
+        let hierarchy = Hierarchy::new(&entries);
+        let (uri, file) = (&entries[0].0, &*entries[0].1);
+        let check = |key: &Key, new_name: &str| conflict(&hierarchy, uri, file, key, new_name);
+        let local = (Some(uri.clone()), Symbol::Local(find(file, "count", 0)));
+        assert_eq!(
+            check(&local, "total"),
+            Some(String::from("'total' is already declared in method 'main'"))
+        );
+        assert_eq!(check(&local, "size"), None);
+        assert_eq!(
+            check(&local, "while"),
+            Some(String::from("'while' is not a valid name"))
+        );
+        assert_eq!(
+            check(&member(uri, "A", "get", true), "other"),
+            Some(String::from("'other' is already declared in class 'B'"))
+        );
+        assert_eq!(
+            check(&member(uri, "B", "get", true), "size"),
+            Some(String::from("'size' is already declared in class 'A'"))
+        );
+        assert_eq!(check(&member(uri, "A", "size", false), "value"), None);
+        assert_eq!(
+            check(&class(uri, "B"), "A"),
+            Some(String::from("class 'A' already exists"))
+        );
+        assert_eq!(check(&class(uri, "A"), "Shape"), None);
+        assert_eq!(
+            check(&class(uri, "Main"), "Program"),
+            Some(String::from(
+                "class 'Main' is the entry point of the program"
+            ))
+        );
+        assert_eq!(
+            check(&member(uri, "Main", "main", true), "start"),
+            Some(String::from(
+                "method 'main' is the entry point of the program"
+            ))
+        );
+    }
+
+    #[test]
+    fn declared_in_several_files() {
+        let entries = files(&[
+            ("file:///a.decaf", PROGRAM),
+            ("file:///b.decaf", PROGRAM),
+            ("file:///c.decaf", "class C extends A {\n}\n"),
+        ]);
+        let hierarchy = Hierarchy::new(&entries);
+        let (a, c) = (&entries[0].0, &entries[2].0);
+        assert_eq!(
+            renamable(&hierarchy, a, &class(a, "A")),
+            Some(String::from("class 'A' is declared in more than one file"))
+        );
+        // members are renamed in the file declaring their class
+        assert_eq!(renamable(&hierarchy, a, &member(a, "A", "get", true)), None);
+        // but `c.decaf` only finds `A` by its name
+        let key = hierarchy.resolve(c, &member(c, "C", "get", true).1);
+        assert_eq!(key, member(a, "A", "get", true));
+        assert_eq!(
+            renamable(&hierarchy, c, &key),
+            Some(String::from("class 'A' is declared in more than one file"))
+        );
+        assert_eq!(
+            renamable(&hierarchy, a, &(None, Symbol::Class(String::from("Nope")))),
+            Some(String::from("'Nope' is not declared in the workspace"))
+        );
+    }
+}
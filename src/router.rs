@@ -0,0 +1,213 @@
+use crate::line_index::PositionEncoding;
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// Requests answered without going through the `LanguageServer` trait
+pub trait Extension: fmt::Debug + Send + Sync {
+    /// Capabilities added to the initialize response
+    fn capabilities(&self) -> Value;
+
+    /// Whether `method` is answered by `request`
+    fn handles(&self, method: &str) -> bool;
+
+    /// Answer a request, or return `None` if `method` is not an extension
+    fn request(&self, method: &str, params: Value) -> Option<Result<Value, String>>;
+}
+
+/// Sits in front of the `LspService` and handles the parts of the protocol
+/// that `lsp-types` does not model yet
+#[derive(Debug)]
+pub struct Router<S> {
+    inner: S,
+    encoding: Arc<RwLock<PositionEncoding>>,
+    extension: Arc<dyn Extension>,
+}
+
+impl<S> Router<S> {
+    pub fn new(
+        inner: S,
+        encoding: Arc<RwLock<PositionEncoding>>,
+        extension: Arc<dyn Extension>,
+    ) -> Router<S> {
+        Router {
+            inner,
+            encoding,
+            extension,
+        }
+    }
+}
+
+/// The method of a message, read without building the rest of it
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(borrow)]
+    method: Option<Cow<'a, str>>,
+}
+
+/// Read `capabilities.general.positionEncodings` from an initialize request
+fn negotiate_encoding(message: &Value) -> PositionEncoding {
+    let offered = message
+        .pointer("/params/capabilities/general/positionEncodings")
+        .and_then(Value::as_array);
+    match offered {
+        Some(offered) => PositionEncoding::negotiate(offered.iter().filter_map(Value::as_str)),
+        None => PositionEncoding::default(),
+    }
+}
+
+/// Announce the negotiated encoding and the capabilities of the extensions in the
+/// initialize response
+fn announce(response: String, encoding: PositionEncoding, extra: Value) -> String {
+    let mut message: Value = match serde_json::from_str(&response) {
+        Ok(message) => message,
+        Err(_) => return response,
+    };
+    if let Some(capabilities) = message
+        .pointer_mut("/result/capabilities")
+        .and_then(Value::as_object_mut)
+    {
+        capabilities.insert(
+            String::from("positionEncoding"),
+            Value::from(encoding.name()),
+        );
+        if let Value::Object(extra) = extra {
+            capabilities.extend(extra);
+        }
+    }
+    message.to_string()
+}
+
+fn reply(id: Value, result: Result<Value, String>) -> String {
+    let message = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        // invalid params
+        Err(message) => json!({
+            "jsonrpc": "2.0",
+            "error": { "code": -32602, "message": message },
+            "id": id,
+        }),
+    };
+    message.to_string()
+}
+
+impl<S> Service<String> for Router<S>
+where
+    S: Service<String, Response = String>,
+    S::Future: Send + 'static,
+{
+    type Response = String;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<String, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: String) -> Self::Future {
+        // most messages go straight through, so only look at their method
+        let method = match serde_json::from_str::<Header>(&request) {
+            Ok(header) => header.method.map(Cow::into_owned),
+            Err(_) => None,
+        };
+        let method = match method {
+            Some(method) if method == "initialize" || self.extension.handles(&method) => method,
+            _ => return Box::pin(self.inner.call(request)),
+        };
+        let message: Value = serde_json::from_str(&request).unwrap_or(Value::Null);
+        match method.as_str() {
+            "initialize" => {
+                let encoding = negotiate_encoding(&message);
+                debug!("position encoding {:?}", encoding);
+                *self.encoding.write().unwrap() = encoding;
+                let extra = self.extension.capabilities();
+                let response = self.inner.call(request);
+                Box::pin(async move { Ok(announce(response.await?, encoding, extra)) })
+            }
+            method => {
+                let params = message.get("params").cloned().unwrap_or(Value::Null);
+                match self.extension.request(method, params) {
+                    Some(result) => {
+                        let id = message.get("id").cloned().unwrap_or(Value::Null);
+                        let response = reply(id, result);
+                        Box::pin(async move { Ok(response) })
+                    }
+                    None => Box::pin(self.inner.call(request)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn method(message: &str) -> Option<String> {
+        let header: Header = serde_json::from_str(message).ok()?;
+        header.method.map(Cow::into_owned)
+    }
+
+    #[test]
+    fn header() {
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"position":{"line":0,"character":0}}}"#;
+        assert_eq!(method(request), Some(String::from("textDocument/hover")));
+        // escapes cannot be borrowed from the message
+        let request = r#"{"jsonrpc":"2.0","method":"$\/cancelRequest","params":{"id":1}}"#;
+        assert_eq!(method(request), Some(String::from("$/cancelRequest")));
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+        assert_eq!(method(response), None);
+    }
+
+    #[test]
+    fn encoding() {
+        let message = json!({
+            "method": "initialize",
+            "params": { "capabilities": { "general": { "positionEncodings": ["utf-16", "utf-8"] } } }
+        });
+        assert_eq!(negotiate_encoding(&message), PositionEncoding::Utf8);
+        let message = json!({ "method": "initialize", "params": { "capabilities": {} } });
+        assert_eq!(negotiate_encoding(&message), PositionEncoding::Utf16);
+    }
+
+    #[test]
+    fn announced() {
+        let response = json!({ "jsonrpc": "2.0", "id": 0, "result": { "capabilities": {} } });
+        let extra = json!({ "typeHierarchyProvider": true });
+        let response = announce(response.to_string(), PositionEncoding::Utf8, extra);
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response["result"]["capabilities"],
+            json!({ "positionEncoding": "utf-8", "typeHierarchyProvider": true })
+        );
+        // anything else is passed through untouched
+        assert_eq!(
+            announce(String::from("oops"), PositionEncoding::Utf8, Value::Null),
+            "oops"
+        );
+    }
+
+    #[test]
+    fn replies() {
+        let replied = |result| serde_json::from_str::<Value>(&reply(json!(7), result)).unwrap();
+        assert_eq!(
+            replied(Ok(json!([]))),
+            json!({ "jsonrpc": "2.0", "result": [], "id": 7 })
+        );
+        assert_eq!(
+            replied(Err(String::from("no such item"))),
+            json!({
+                "jsonrpc": "2.0",
+                "error": { "code": -32602, "message": "no such item" },
+                "id": 7
+            })
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::line_index::LineIndex;
+use tower_lsp::lsp_types::*;
+
+/// The edit that turns one version of a file into another, found by trimming the
+/// common prefix and suffix of both texts. Used to move ranges computed on an
+/// older version onto the current text.
+#[derive(Debug, Clone, Copy)]
+pub struct Shift {
+    /// Replaced range in the old text
+    old: Range,
+    /// End of the replacement in the new text
+    new_end: Position,
+}
+
+impl Shift {
+    pub fn between(old: &LineIndex, new: &LineIndex) -> Shift {
+        let (a, b) = (old.text().as_bytes(), new.text().as_bytes());
+        let mut prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+        while !old.text().is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+        let max_suffix = a.len().min(b.len()) - prefix;
+        let mut suffix = a
+            .iter()
+            .rev()
+            .zip(b.iter().rev())
+            .take(max_suffix)
+            .take_while(|(x, y)| x == y)
+            .count();
+        while !old.text().is_char_boundary(a.len() - suffix) {
+            suffix -= 1;
+        }
+        Shift {
+            old: Range {
+                start: old.offset_position(prefix),
+                end: old.offset_position(a.len() - suffix),
+            },
+            new_end: new.offset_position(b.len() - suffix),
+        }
+    }
+
+    /// Range of the replacement in the new text
+    pub fn edited(&self) -> Range {
+        Range {
+            start: self.old.start,
+            end: self.new_end,
+        }
+    }
+
+    /// Smallest range of the new text covering both the edit and `range` of the old text
+    pub fn cover(&self, range: Range) -> Range {
+        Range {
+            start: if range.start < self.old.start {
+                range.start
+            } else {
+                self.old.start
+            },
+            end: if range.end > self.old.end {
+                self.after(range.end)
+            } else {
+                self.new_end
+            },
+        }
+    }
+
+    /// Map a position at or after the end of the edit
+    fn after(&self, position: Position) -> Position {
+        if position.line == self.old.end.line {
+            Position {
+                line: self.new_end.line,
+                character: self.new_end.character + position.character - self.old.end.character,
+            }
+        } else {
+            Position {
+                line: position.line - self.old.end.line + self.new_end.line,
+                character: position.character,
+            }
+        }
+    }
+
+    /// Map a range of the old text onto the new one. Ranges enclosing the edit
+    /// grow or shrink with it; ranges partially overlapping it are lost.
+    pub fn range(&self, range: Range) -> Option<Range> {
+        if range.end <= self.old.start {
+            Some(range)
+        } else if range.start >= self.old.end {
+            Some(Range {
+                start: self.after(range.start),
+                end: self.after(range.end),
+            })
+        } else if range.start <= self.old.start && range.end >= self.old.end {
+            Some(Range {
+                start: range.start,
+                end: self.after(range.end),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn folding_range(&self, folding: &FoldingRange) -> Option<FoldingRange> {
+        let range = self.range(Range {
+            start: Position {
+                line: folding.start_line,
+                character: folding.start_character.unwrap_or(0),
+            },
+            end: Position {
+                line: folding.end_line,
+                character: folding.end_character.unwrap_or(0),
+            },
+        })?;
+        Some(FoldingRange {
+            start_line: range.start.line,
+            end_line: range.end.line,
+            ..folding.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_index::PositionEncoding;
+
+    fn shift(old: &str, new: &str) -> Shift {
+        Shift::between(
+            &LineIndex::new(old, PositionEncoding::Utf16),
+            &LineIndex::new(new, PositionEncoding::Utf16),
+        )
+    }
+
+    fn range(start: (u64, u64), end: (u64, u64)) -> Range {
+        Range {
+            start: Position {
+                line: start.0,
+                character: start.1,
+            },
+            end: Position {
+                line: end.0,
+                character: end.1,
+            },
+        }
+    }
+
+    #[test]
+    fn unchanged() {
+        let edit = shift("int x;\nint y;", "int x;\nint y;");
+        assert_eq!(edit.edited(), range((1, 6), (1, 6)));
+        assert_eq!(
+            edit.range(range((0, 4), (1, 5))),
+            Some(range((0, 4), (1, 5)))
+        );
+    }
+
+    #[test]
+    fn inserted_line() {
+        let edit = shift("int x;\nint y;", "int x;\n\nint y;");
+        assert_eq!(edit.edited(), range((1, 0), (2, 0)));
+        assert_eq!(
+            edit.range(range((0, 4), (0, 5))),
+            Some(range((0, 4), (0, 5)))
+        );
+        assert_eq!(
+            edit.range(range((1, 4), (1, 5))),
+            Some(range((2, 4), (2, 5)))
+        );
+    }
+
+    #[test]
+    fn deleted_lines() {
+        let edit = shift("a\nb\nc\nd", "a\nd");
+        assert_eq!(
+            edit.range(range((3, 0), (3, 1))),
+            Some(range((1, 0), (1, 1)))
+        );
+        assert_eq!(edit.range(range((1, 0), (1, 1))), None);
+    }
+
+    #[test]
+    fn overlapping() {
+        let edit = shift("abcdef", "abXYZef");
+        assert_eq!(edit.edited(), range((0, 2), (0, 5)));
+        // partially overlapping ranges are lost, enclosing ones grow
+        assert_eq!(edit.range(range((0, 1), (0, 3))), None);
+        assert_eq!(edit.range(range((0, 3), (0, 5))), None);
+        assert_eq!(
+            edit.range(range((0, 0), (0, 6))),
+            Some(range((0, 0), (0, 7)))
+        );
+        assert_eq!(edit.cover(range((0, 0), (0, 1))), range((0, 0), (0, 5)));
+        assert_eq!(edit.cover(range((0, 5), (0, 6))), range((0, 2), (0, 7)));
+    }
+
+    #[test]
+    fn multi_byte() {
+        // 'é' and 'è' share their first byte, which must not split the edit
+        let edit = shift("aé b", "aè b");
+        assert_eq!(edit.edited(), range((0, 1), (0, 2)));
+        assert_eq!(
+            edit.range(range((0, 3), (0, 4))),
+            Some(range((0, 3), (0, 4)))
+        );
+
+        let edit = shift("s = \"é\"; x", "s = \"éé\"; x");
+        assert_eq!(
+            edit.range(range((0, 9), (0, 10))),
+            Some(range((0, 10), (0, 11)))
+        );
+    }
+}
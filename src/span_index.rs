@@ -0,0 +1,191 @@
+use tower_lsp::lsp_types::*;
+
+/// Spans of one file sorted by start, answering "innermost span at a position".
+///
+/// Spans are pushed while walking the AST and sorted once by `build`. Each span
+/// remembers the closest earlier span that encloses it, so a lookup is a binary
+/// search followed by a walk up the (shallow) nesting chain.
+#[derive(Debug, Clone)]
+pub struct SpanIndex<T> {
+    spans: Vec<(Range, T)>,
+    parents: Vec<Option<usize>>,
+}
+
+impl<T> Default for SpanIndex<T> {
+    fn default() -> SpanIndex<T> {
+        SpanIndex {
+            spans: Vec::new(),
+            parents: Vec::new(),
+        }
+    }
+}
+
+fn contains(range: &Range, position: Position) -> bool {
+    range.start <= position && range.end >= position
+}
+
+fn encloses(outer: &Range, inner: &Range) -> bool {
+    outer.start <= inner.start && outer.end >= inner.end
+}
+
+impl<T> SpanIndex<T> {
+    pub fn push(&mut self, range: Range, value: T) {
+        self.spans.push((range, value));
+    }
+
+    /// Sort the spans and link each to its enclosing span. Must be called after the last `push`.
+    pub fn build(&mut self) {
+        // stable, so that of two equal spans the later one is treated as the inner one
+        self.spans
+            .sort_by(|(a, _), (b, _)| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        self.parents.clear();
+        let mut stack: Vec<usize> = Vec::new();
+        for i in 0..self.spans.len() {
+            while let Some(&top) = stack.last() {
+                if encloses(&self.spans[top].0, &self.spans[i].0) {
+                    break;
+                }
+                stack.pop();
+            }
+            self.parents.push(stack.last().cloned());
+            stack.push(i);
+        }
+    }
+
+    /// Innermost span containing `position`, ends inclusive
+    pub fn innermost(&self, position: Position) -> Option<&(Range, T)> {
+        debug_assert_eq!(self.spans.len(), self.parents.len());
+        let after = self
+            .spans
+            .binary_search_by(|(range, _)| {
+                if range.start <= position {
+                    std::cmp::Ordering::Less
+                } else {
+                    std::cmp::Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|i| i);
+        let mut current = after.checked_sub(1);
+        while let Some(i) = current {
+            if contains(&self.spans[i].0, position) {
+                return Some(&self.spans[i]);
+            }
+            current = self.parents[i];
+        }
+        None
+    }
+
+    /// Map every span to a new one, dropping those for which `f` returns `None`
+    pub fn filter_map<U>(
+        &self,
+        mut f: impl FnMut(&Range, &T) -> Option<(Range, U)>,
+    ) -> SpanIndex<U> {
+        let mut index = SpanIndex::default();
+        for (range, value) in self.spans.iter() {
+            if let Some((range, value)) = f(range, value) {
+                index.push(range, value);
+            }
+        }
+        index.build();
+        index
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Range, T)> {
+        self.spans.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> Range {
+        Range {
+            start: Position {
+                line: 0,
+                character: start,
+            },
+            end: Position {
+                line: 0,
+                character: end,
+            },
+        }
+    }
+
+    fn at(index: &SpanIndex<&'static str>, character: u64) -> Option<&'static str> {
+        index
+            .innermost(Position { line: 0, character })
+            .map(|(_, value)| *value)
+    }
+
+    #[test]
+    fn nested() {
+        let mut index = SpanIndex::default();
+        index.push(range(12, 15), "second");
+        index.push(range(0, 20), "outer");
+        index.push(range(5, 10), "first");
+        index.build();
+        assert_eq!(at(&index, 7), Some("first"));
+        assert_eq!(at(&index, 10), Some("first"));
+        // past a nested span, fall back to the one enclosing it
+        assert_eq!(at(&index, 11), Some("outer"));
+        assert_eq!(at(&index, 13), Some("second"));
+        assert_eq!(at(&index, 20), Some("outer"));
+        assert_eq!(at(&index, 21), None);
+    }
+
+    #[test]
+    fn overlapping() {
+        let mut index = SpanIndex::default();
+        index.push(range(0, 10), "left");
+        index.push(range(5, 15), "right");
+        index.build();
+        assert_eq!(at(&index, 3), Some("left"));
+        assert_eq!(at(&index, 7), Some("right"));
+        assert_eq!(at(&index, 12), Some("right"));
+        assert_eq!(at(&index, 16), None);
+    }
+
+    #[test]
+    fn equal() {
+        let mut index = SpanIndex::default();
+        index.push(range(0, 5), "first");
+        index.push(range(0, 5), "second");
+        index.build();
+        assert_eq!(at(&index, 2), Some("second"));
+    }
+
+    #[test]
+    fn empty() {
+        let mut index: SpanIndex<&'static str> = SpanIndex::default();
+        assert_eq!(at(&index, 0), None);
+        index.build();
+        assert!(index.is_empty());
+        assert_eq!(at(&index, 0), None);
+    }
+
+    #[test]
+    fn filter_map() {
+        let mut index = SpanIndex::default();
+        index.push(range(0, 20), "outer");
+        index.push(range(5, 10), "inner");
+        index.build();
+        let index = index.filter_map(|range, value| {
+            if *value == "inner" {
+                None
+            } else {
+                Some((*range, *value))
+            }
+        });
+        assert_eq!(index.len(), 1);
+        assert_eq!(at(&index, 7), Some("outer"));
+    }
+}
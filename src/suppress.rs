@@ -0,0 +1,226 @@
+use crate::diagnostics::SOURCE;
+use crate::line_index::LineIndex;
+use crate::lint::UNUSED_SUPPRESSION;
+use crate::tokens::{self, Tok};
+use tower_lsp::lsp_types::*;
+
+const IGNORE: &str = "decaf-ignore:";
+const IGNORE_FILE: &str = "decaf-ignore-file:";
+
+/// One code named by a suppression comment
+#[derive(Debug)]
+struct Suppression {
+    code: String,
+    /// Where the code is written in the comment
+    range: Range,
+    /// Line whose diagnostics are suppressed, or `None` for the whole file
+    line: Option<u64>,
+    used: bool,
+}
+
+/// The `// decaf-ignore: <code>` and `// decaf-ignore-file: <code>` comments of a file.
+/// The first applies to the following line, the second to the whole file when it
+/// appears before any code.
+#[derive(Debug, Default)]
+pub struct Suppressions {
+    suppressions: Vec<Suppression>,
+}
+
+/// Codes separated by commas or spaces, with their byte offsets given that of `text`
+fn codes(text: &str, offset: usize) -> Vec<(usize, &str)> {
+    let mut codes = Vec::new();
+    let mut start = None;
+    for (i, ch) in text.char_indices().chain(Some((text.len(), ' '))) {
+        let separator = ch == ',' || ch.is_whitespace();
+        match start {
+            Some(begin) if separator => {
+                codes.push((offset + begin, &text[begin..i]));
+                start = None;
+            }
+            None if !separator => start = Some(i),
+            _ => {}
+        }
+    }
+    codes
+}
+
+impl Suppressions {
+    /// Read the suppressions from the comments between the lexer `tokens` of the file
+    pub fn scan(index: &LineIndex, tokens: &[Tok]) -> Suppressions {
+        let text = index.text();
+        let code = tokens.first().map_or(text.len(), |tok| tok.start);
+        let mut suppressions = Vec::new();
+        for (start, end) in tokens::comments(text, tokens) {
+            let body = text[start + 2..end].trim_start();
+            let body_start = end - body.len();
+            let (target, rest) = if body.starts_with(IGNORE_FILE) {
+                if start > code {
+                    continue;
+                }
+                (None, body_start + IGNORE_FILE.len())
+            } else if body.starts_with(IGNORE) {
+                let line = index.offset_position(start).line;
+                (Some(line + 1), body_start + IGNORE.len())
+            } else {
+                continue;
+            };
+            for (at, code) in codes(&text[rest..end], rest) {
+                suppressions.push(Suppression {
+                    code: String::from(code),
+                    range: Range {
+                        start: index.offset_position(at),
+                        end: index.offset_position(at + code.len()),
+                    },
+                    line: target,
+                    used: false,
+                });
+            }
+        }
+        Suppressions { suppressions }
+    }
+
+    /// Drop the suppressed diagnostics. With `report_unused`, add a hint for each
+    /// suppression that matched nothing; it is off while some diagnostics are
+    /// missing, e.g. when the file did not parse.
+    pub fn apply(&mut self, diagnostics: Vec<Diagnostic>, report_unused: bool) -> Vec<Diagnostic> {
+        let mut kept = Vec::new();
+        for diagnostic in diagnostics {
+            let code = match &diagnostic.code {
+                Some(NumberOrString::String(code)) => code,
+                _ => {
+                    kept.push(diagnostic);
+                    continue;
+                }
+            };
+            let line = diagnostic.range.start.line;
+            let suppression = self.suppressions.iter_mut().find(|suppression| {
+                suppression.code == *code && suppression.line.map_or(true, |target| target == line)
+            });
+            match suppression {
+                Some(suppression) => suppression.used = true,
+                None => kept.push(diagnostic),
+            }
+        }
+        if report_unused {
+            for suppression in self.suppressions.iter().filter(|s| !s.used) {
+                kept.push(Diagnostic {
+                    range: suppression.range,
+                    severity: Some(DiagnosticSeverity::Hint),
+                    code: Some(NumberOrString::String(String::from(UNUSED_SUPPRESSION))),
+                    source: Some(String::from(SOURCE)),
+                    message: format!("unused suppression of '{}'", suppression.code),
+                    related_information: None,
+                    tags: Some(vec![DiagnosticTag::Unnecessary]),
+                });
+            }
+        }
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_index::PositionEncoding;
+
+    fn diagnostic(code: &str, line: u64) -> Diagnostic {
+        Diagnostic {
+            range: Range {
+                start: Position { line, character: 0 },
+                end: Position { line, character: 1 },
+            },
+            severity: Some(DiagnosticSeverity::Warning),
+            code: Some(NumberOrString::String(String::from(code))),
+            source: None,
+            message: String::new(),
+            related_information: None,
+            tags: None,
+        }
+    }
+
+    fn suppressions(text: &str) -> (LineIndex, Suppressions) {
+        let index = LineIndex::new(text, PositionEncoding::Utf16);
+        let tokens = tokens::lex(text, &index);
+        let suppressions = Suppressions::scan(&index, &tokens);
+        (index, suppressions)
+    }
+
+    fn summary(diagnostics: &[Diagnostic]) -> Vec<(&str, u64)> {
+        diagnostics
+            .iter()
+            .map(|diagnostic| match &diagnostic.code {
+                Some(NumberOrString::String(code)) => (code.as_str(), diagnostic.range.start.line),
+                _ => ("", diagnostic.range.start.line),
+            })
+            .collect()
+    }
+
+    const TEXT: &str = "// decaf-ignore-file: unused-parameter
+class Main {
+    // decaf-ignore: unused-variable, self-assignment
+    int x;
+    string s = \"// decaf-ignore: syntax-error\";
+}
+";
+
+    #[test]
+    fn matching() {
+        let (index, mut suppressions) = suppressions(TEXT);
+        let kept = suppressions.apply(
+            vec![
+                diagnostic("unused-parameter", 4),
+                diagnostic("unused-variable", 3),
+                diagnostic("unused-variable", 4),
+                diagnostic("syntax-error", 5),
+            ],
+            true,
+        );
+        // a suppression applies to the next line only, and not from inside a string
+        assert_eq!(
+            summary(&kept),
+            vec![
+                ("unused-variable", 4),
+                ("syntax-error", 5),
+                (UNUSED_SUPPRESSION, 2)
+            ]
+        );
+        let at = TEXT.find("self-assignment").unwrap();
+        assert_eq!(kept[2].range.start, index.offset_position(at));
+        assert_eq!(kept[2].tags, Some(vec![DiagnosticTag::Unnecessary]));
+    }
+
+    #[test]
+    fn unused_not_reported() {
+        let (_, mut suppressions) = suppressions(TEXT);
+        let kept = suppressions.apply(vec![diagnostic("unused-variable", 3)], false);
+        assert!(kept.is_empty());
+    }
+
+    #[test]
+    fn file_after_code() {
+        let (_, mut suppressions) =
+            suppressions("class Main {}\n// decaf-ignore-file: no-return\n");
+        let kept = suppressions.apply(vec![diagnostic("no-return", 0)], true);
+        assert_eq!(summary(&kept), vec![("no-return", 0)]);
+    }
+
+    #[test]
+    fn multi_byte() {
+        // 'é' is 2 bytes but 1 UTF-16 unit
+        let (_, mut suppressions) = suppressions("\"é\" // decaf-ignore: bogus\n");
+        let kept = suppressions.apply(Vec::new(), true);
+        assert_eq!(
+            kept[0].range,
+            Range {
+                start: Position {
+                    line: 0,
+                    character: 21
+                },
+                end: Position {
+                    line: 0,
+                    character: 26
+                },
+            }
+        );
+    }
+}
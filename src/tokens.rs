@@ -0,0 +1,130 @@
+use crate::line_index::LineIndex;
+use common::Loc;
+use syntax::parser::{Lexer, TokenKind};
+
+/// A lexer token located by byte offsets into the text
+#[derive(Debug, Clone, Copy)]
+pub struct Tok {
+    pub ty: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Lex the whole text, ending with an `_Eof` token
+pub fn lex(text: &str, index: &LineIndex) -> Vec<Tok> {
+    let mut lexer = Lexer::new(text.as_bytes());
+    let mut tokens = Vec::new();
+    loop {
+        let tok = lexer.next();
+        if tok.ty == TokenKind::_Eof {
+            tokens.push(Tok {
+                ty: tok.ty,
+                start: text.len(),
+                end: text.len(),
+            });
+            return tokens;
+        }
+        let start = index.loc_offset(&Loc(tok.line, tok.col));
+        tokens.push(Tok {
+            ty: tok.ty,
+            start,
+            end: start + tok.piece.len(),
+        });
+    }
+}
+
+/// Byte spans of the `//` comments, up to the end of their line. The lexer skips
+/// comments, but the text between its tokens holds nothing else besides whitespace,
+/// so a `//` there always starts one.
+pub fn comments(text: &str, tokens: &[Tok]) -> Vec<(usize, usize)> {
+    let mut comments = Vec::new();
+    let mut gap = 0;
+    for tok in tokens.iter() {
+        let mut at = gap;
+        while let Some(found) = text[at..tok.start].find("//") {
+            let start = at + found;
+            let end = text[start..tok.start]
+                .find('\n')
+                .map_or(tok.start, |len| start + len);
+            comments.push((start, end));
+            at = end;
+        }
+        gap = tok.end;
+    }
+    comments
+}
+
+/// Index of the token covering or following byte `offset`
+pub fn at(tokens: &[Tok], offset: usize) -> usize {
+    // tokens are sorted, so those ending at or before `offset` come first
+    let after = tokens
+        .binary_search_by(|tok| {
+            if tok.end <= offset {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Greater
+            }
+        })
+        .unwrap_or_else(|i| i);
+    after.min(tokens.len() - 1)
+}
+
+/// Byte span of the token at `offset`. A `.` selects the name after it.
+pub fn token_span(tokens: &[Tok], offset: usize) -> (usize, usize) {
+    let mut i = at(tokens, offset);
+    if tokens[i].ty == TokenKind::Dot && tokens.get(i + 1).map(|tok| tok.ty) == Some(TokenKind::Id)
+    {
+        i += 1;
+    }
+    (tokens[i].start, tokens[i].end)
+}
+
+/// Byte span of the expression starting at `offset`, up to the first unbalanced
+/// closing bracket or a separator outside brackets
+pub fn expr_span(tokens: &[Tok], offset: usize) -> (usize, usize) {
+    use TokenKind::*;
+    let first = at(tokens, offset);
+    let mut last = first;
+    let mut depth = 0;
+    for (i, tok) in tokens.iter().enumerate().skip(first) {
+        match tok.ty {
+            LPar | LBrk => depth += 1,
+            RPar | RBrk if depth == 0 => break,
+            RPar | RBrk => depth -= 1,
+            Comma | Semi | Assign | LBrc | RBrc if depth == 0 => break,
+            _Eof => break,
+            _ => {}
+        }
+        last = i;
+    }
+    (tokens[first].start, tokens[last].end)
+}
+
+/// Byte span of the statement starting at `offset`: up to its `;` or the brace
+/// closing its last block, including any `else` branch
+pub fn stmt_span(tokens: &[Tok], offset: usize) -> (usize, usize) {
+    use TokenKind::*;
+    let first = at(tokens, offset);
+    let mut last = first;
+    let mut depth = 0;
+    for (i, tok) in tokens.iter().enumerate().skip(first) {
+        match tok.ty {
+            _Eof => break,
+            LPar | LBrk | LBrc => depth += 1,
+            RPar | RBrk | RBrc => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            break;
+        }
+        last = i;
+        let ends = match tok.ty {
+            Semi | RBrc => depth == 0,
+            _ => false,
+        };
+        if ends && tokens.get(i + 1).map(|tok| tok.ty) != Some(Else) {
+            break;
+        }
+    }
+    (tokens[first].start, tokens[last].end)
+}
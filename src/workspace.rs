@@ -0,0 +1,77 @@
+use log::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::*;
+
+/// Workspace roots announced by the client, preferring workspace folders over the root uri
+pub fn roots(params: &InitializeParams) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    if let Some(folders) = &params.workspace_folders {
+        for folder in folders.iter() {
+            if let Ok(path) = folder.uri.to_file_path() {
+                roots.push(path);
+            }
+        }
+    }
+    if roots.is_empty() {
+        if let Some(path) = params
+            .root_uri
+            .as_ref()
+            .and_then(|uri| uri.to_file_path().ok())
+        {
+            roots.push(path);
+        } else if let Some(path) = &params.root_path {
+            roots.push(PathBuf::from(path));
+        }
+    }
+    roots
+}
+
+pub fn is_decaf(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "decaf")
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| name.starts_with('.'))
+}
+
+/// Recursively collect `.decaf` files under `root`, skipping hidden directories
+pub fn scan(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("cannot read {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(ty) if ty.is_dir() && !is_hidden(&path) => pending.push(path),
+                Ok(ty) if ty.is_file() && is_decaf(&path) => files.push(path),
+                _ => {}
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Contents of every `.decaf` file under `roots`, skipping those that cannot be read
+pub fn read(roots: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    let mut sources = Vec::new();
+    for root in roots.iter() {
+        for path in scan(root) {
+            match fs::read_to_string(&path) {
+                Ok(content) => sources.push((path, content)),
+                Err(err) => warn!("cannot read {}: {}", path.display(), err),
+            }
+        }
+    }
+    sources
+}